@@ -0,0 +1,197 @@
+//! Expansion of `#[cycle::main]` and `#[cycle::test]`
+
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::{Expr, ExprLit, ItemFn, Lit, MetaNameValue, ReturnType, Token};
+
+/// Which attribute is being expanded
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Main,
+    Test,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Flavor {
+    CurrentThread,
+    MultiThread,
+}
+
+/// Options given to the attribute
+struct Config {
+    flavor: Flavor,
+    worker_threads: Option<usize>,
+    start_paused: bool,
+    krate: syn::Path,
+}
+
+/// Rewrite the `async fn` in `item` into a sync function that runs it on a runtime
+pub(crate) fn expand(kind: Kind, args: TokenStream, item: TokenStream) -> syn::Result<TokenStream> {
+    let mut input: ItemFn = syn::parse2(item)?;
+    let config = Config::parse(kind, args)?;
+
+    if input.sig.asyncness.take().is_none() {
+        return Err(syn::Error::new_spanned(
+            input.sig.fn_token,
+            "the `async` keyword is missing from the function declaration",
+        ));
+    }
+
+    if !input.sig.inputs.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.sig.inputs,
+            format!("`#[cycle::{}]` functions cannot take arguments", kind.name()),
+        ));
+    }
+
+    if kind == Kind::Test {
+        if let Some(attr) = input.attrs.iter().find(|attr| attr.path().is_ident("test")) {
+            return Err(syn::Error::new_spanned(attr, "`#[cycle::test]` already marks the function as a test"));
+        }
+    }
+
+    let krate = &config.krate;
+    let mut builder = match config.flavor {
+        Flavor::CurrentThread => quote!(#krate::runtime::Builder::new_current_thread()),
+        Flavor::MultiThread => quote!(#krate::runtime::Builder::new_multi_thread()),
+    };
+    if let Some(worker_threads) = config.worker_threads {
+        builder.extend(quote!(.worker_threads(#worker_threads)));
+    }
+    if config.start_paused {
+        builder.extend(quote!(.start_paused(true)));
+    }
+
+    let output = match &input.sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ty) => ty.to_token_stream(),
+    };
+
+    // The annotation lets `?` in the body infer its error type from the signature
+    let body = &input.block;
+    input.block = syn::parse2(quote! {{
+        let body = async #body;
+        let body: ::std::pin::Pin<&mut dyn ::std::future::Future<Output = #output>> = ::std::pin::pin!(body);
+        #builder
+            .enable_all()
+            .build()
+            .expect("failed to build the cycle runtime")
+            .block_on(body)
+    }})?;
+
+    let test_attr = match kind {
+        Kind::Main => quote!(),
+        Kind::Test => quote!(#[::core::prelude::v1::test]),
+    };
+
+    Ok(quote! {
+        #test_attr
+        #input
+    })
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Main => "main",
+            Kind::Test => "test",
+        }
+    }
+
+    /// Programs get all cores by default, tests a deterministic single thread
+    fn default_flavor(self) -> Flavor {
+        match self {
+            Kind::Main => Flavor::MultiThread,
+            Kind::Test => Flavor::CurrentThread,
+        }
+    }
+}
+
+impl Config {
+    fn parse(kind: Kind, args: TokenStream) -> syn::Result<Self> {
+        let args = Punctuated::<MetaNameValue, Token![,]>::parse_terminated.parse2(args)?;
+
+        let mut flavor = None;
+        let mut worker_threads = None;
+        let mut start_paused = false;
+        let mut krate = None;
+
+        for arg in &args {
+            let name = arg
+                .path
+                .get_ident()
+                .map(ToString::to_string)
+                .unwrap_or_default();
+
+            match name.as_str() {
+                "flavor" => {
+                    let value = lit_str(&arg.value)?;
+                    flavor = Some(match value.value().as_str() {
+                        "current_thread" => Flavor::CurrentThread,
+                        "multi_thread" => Flavor::MultiThread,
+                        _ => {
+                            return Err(syn::Error::new_spanned(
+                                value,
+                                "expected `\"current_thread\"` or `\"multi_thread\"`",
+                            ))
+                        }
+                    });
+                }
+                "worker_threads" => {
+                    let value = match &arg.value {
+                        Expr::Lit(ExprLit { lit: Lit::Int(value), .. }) => value,
+                        value => return Err(syn::Error::new_spanned(value, "expected an integer")),
+                    };
+                    let threads: usize = value.base10_parse()?;
+                    if threads == 0 {
+                        return Err(syn::Error::new_spanned(value, "`worker_threads` must be greater than 0"));
+                    }
+                    worker_threads = Some(threads);
+                }
+                "start_paused" => {
+                    start_paused = match &arg.value {
+                        Expr::Lit(ExprLit { lit: Lit::Bool(value), .. }) => value.value,
+                        value => return Err(syn::Error::new_spanned(value, "expected `true` or `false`")),
+                    };
+                }
+                "crate" => krate = Some(lit_str(&arg.value)?.parse()?),
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        &arg.path,
+                        "unknown option, expected one of `flavor`, `worker_threads`, `start_paused` or `crate`",
+                    ))
+                }
+            }
+        }
+
+        let flavor = flavor.unwrap_or(kind.default_flavor());
+        if worker_threads.is_some() && flavor == Flavor::CurrentThread {
+            return Err(syn::Error::new_spanned(
+                &args,
+                "`worker_threads` requires `flavor = \"multi_thread\"`",
+            ));
+        }
+        if start_paused && flavor == Flavor::MultiThread {
+            return Err(syn::Error::new_spanned(
+                &args,
+                "`start_paused` requires `flavor = \"current_thread\"`",
+            ));
+        }
+
+        Ok(Self {
+            flavor,
+            worker_threads,
+            start_paused,
+            krate: krate.unwrap_or_else(|| syn::parse_quote!(::cycle)),
+        })
+    }
+}
+
+fn lit_str(value: &Expr) -> syn::Result<&syn::LitStr> {
+    match value {
+        Expr::Lit(ExprLit { lit: Lit::Str(value), .. }) => Ok(value),
+        value => Err(syn::Error::new_spanned(value, "expected a string literal")),
+    }
+}
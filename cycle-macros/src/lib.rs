@@ -0,0 +1,70 @@
+//! Attribute macros for the CYCLE async runtime
+//!
+//! These are re-exported by `cycle` behind its `macros` feature; depend on
+//! `cycle` rather than on this crate directly.
+
+use proc_macro::TokenStream;
+
+mod entry;
+
+/// Run an `async fn` as the program's entry point
+///
+/// The function body becomes the root future of a runtime built for it, so
+///
+/// ```ignore
+/// #[cycle::main]
+/// async fn main() -> std::io::Result<()> {
+///     cycle::time::sleep(std::time::Duration::from_millis(10)).await;
+///     Ok(())
+/// }
+/// ```
+///
+/// expands to roughly
+///
+/// ```ignore
+/// fn main() -> std::io::Result<()> {
+///     cycle::runtime::Builder::new_multi_thread()
+///         .enable_all()
+///         .build()
+///         .expect("failed to build the cycle runtime")
+///         .block_on(async {
+///             cycle::time::sleep(std::time::Duration::from_millis(10)).await;
+///             Ok(())
+///         })
+/// }
+/// ```
+///
+/// # Options
+///
+/// - `flavor = "multi_thread"` (the default) or `"current_thread"`
+/// - `worker_threads = N` sets the worker count of a multi-threaded runtime
+/// - `start_paused = true` starts a current-thread runtime with its clock
+///   paused, see `cycle::time::pause`
+/// - `crate = "path"` names the `cycle` crate if it was renamed
+#[proc_macro_attribute]
+pub fn main(args: TokenStream, item: TokenStream) -> TokenStream {
+    entry::expand(entry::Kind::Main, args.into(), item.into())
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Run an `async fn` as a test
+///
+/// Each test gets a fresh runtime, which is a current-thread runtime unless
+/// `flavor = "multi_thread"` is given. Accepts the same options as
+/// [`macro@main`].
+///
+/// ```ignore
+/// #[cycle::test(start_paused = true)]
+/// async fn times_out() {
+///     let slow = cycle::time::sleep(std::time::Duration::from_secs(60));
+///     let result = cycle::time::timeout(std::time::Duration::from_secs(1), slow).await;
+///     assert!(result.is_err());
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn test(args: TokenStream, item: TokenStream) -> TokenStream {
+    entry::expand(entry::Kind::Test, args.into(), item.into())
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
@@ -4,7 +4,7 @@ use std::time::Instant;
 fn main() {
     println!("🔥 CYCLE Performance Benchmark");
     
-    let rt = Runtime::new();
+    let rt = Runtime::new().expect("Failed to build runtime");
     
     // Benchmark task spawning
     let start = Instant::now();
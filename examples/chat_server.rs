@@ -1,17 +1,14 @@
 //! Simple echo chat server - each client gets their own echo
 
 use cycle::prelude::*;
-use cycle::{block_on, spawn};
+use cycle::spawn;
 use cycle::io::{AsyncReadExt, AsyncWriteExt};
 use std::io;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-fn main() -> io::Result<()> {
-    block_on(async_main())
-}
-
-async fn async_main() -> io::Result<()> {
+#[cycle::main]
+async fn main() -> io::Result<()> {
     println!("🔥 CYCLE Echo Chat Server v0.2.0");
     println!("⚡ Listening on 127.0.0.1:8081");
     println!("📡 Connect with: telnet 127.0.0.1 8081");
@@ -5,7 +5,7 @@ use std::time::Instant;
 fn benchmark_cycle() -> std::time::Duration {
     use cycle::prelude::*;
     
-    let rt = Runtime::new();
+    let rt = Runtime::new().expect("Failed to build runtime");
     let start = Instant::now();
     
     rt.block_on(async {
@@ -4,11 +4,8 @@ use cycle::prelude::*;
 use cycle::io::{AsyncReadExt, AsyncWriteExt};
 use std::io;
 
-fn main() -> io::Result<()> {
-    cycle::block_on(async_main())
-}
-
-async fn async_main() -> io::Result<()> {
+#[cycle::main]
+async fn main() -> io::Result<()> {
     println!("🔥 CYCLE Echo Server v0.2.0");
     println!("⚡ Listening on 127.0.0.1:8080");
     println!("📡 Connect with: telnet 127.0.0.1 8080");
@@ -3,11 +3,8 @@
 use cycle::prelude::*;
 use std::io::{self, Write, Read};
 
-fn main() -> io::Result<()> {
-    cycle::block_on(async_main())
-}
-
-async fn async_main() -> io::Result<()> {
+#[cycle::main]
+async fn main() -> io::Result<()> {
     println!("🔥 CYCLE HTTP Client v0.2.0");
     
     // Connect to a web server
@@ -1,7 +1,7 @@
 //! Stress test to push CYCLE to its limits (Debug Version)
 
 use cycle::prelude::*;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 
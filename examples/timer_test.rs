@@ -3,11 +3,8 @@
 use cycle::time::{interval, timeout, yield_now, sleep};
 use std::time::{Duration, Instant};
 
-fn main() {
-    cycle::block_on(async_main());
-}
-
-async fn async_main() {
+#[cycle::main]
+async fn main() {
     println!("🔥 CYCLE Timer Test v0.2.0");
     
     println!("⏰ Testing sleep...");
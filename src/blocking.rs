@@ -0,0 +1,239 @@
+//! Thread pool for blocking operations
+
+use crate::task::{self, JoinError, JoinHandle, Meta, TaskControl};
+use std::collections::VecDeque;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Default time an idle blocking thread waits for work before exiting
+pub(crate) const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+/// Job queued on the blocking pool
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Dynamically sized pool of threads for blocking work
+///
+/// Threads are spawned on demand up to `max_threads`; once that cap is reached
+/// jobs wait in a FIFO queue. Idle threads exit after `keep_alive`.
+#[derive(Clone)]
+pub(crate) struct BlockingPool {
+    inner: Arc<Inner>,
+}
+
+/// Pool configuration, taken from the runtime builder
+pub(crate) struct PoolConfig {
+    /// Prefix for blocking thread names
+    pub(crate) thread_name: String,
+
+    /// Stack size for blocking threads
+    pub(crate) stack_size: Option<usize>,
+
+    /// Upper bound on live threads
+    pub(crate) max_threads: usize,
+
+    /// How long an idle thread lingers before exiting
+    pub(crate) keep_alive: Duration,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    condvar: Condvar,
+    config: PoolConfig,
+}
+
+struct State {
+    /// Jobs waiting for a thread
+    queue: VecDeque<Job>,
+
+    /// Live threads, busy or idle
+    num_threads: usize,
+
+    /// Threads waiting on the condvar
+    num_idle: usize,
+
+    /// Idle threads that have been handed a job but not woken yet
+    num_notify: usize,
+
+    /// Set once the pool stops accepting work
+    shutdown: bool,
+
+    /// Id for the next spawned thread
+    next_id: usize,
+
+    /// Handles of live threads, joined on shutdown
+    threads: Vec<(usize, thread::JoinHandle<()>)>,
+}
+
+impl BlockingPool {
+    /// Create an empty pool; threads are only spawned once work arrives
+    pub(crate) fn new(config: PoolConfig) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State {
+                    queue: VecDeque::new(),
+                    num_threads: 0,
+                    num_idle: 0,
+                    num_notify: 0,
+                    shutdown: false,
+                    next_id: 0,
+                    threads: Vec::new(),
+                }),
+                condvar: Condvar::new(),
+                config,
+            }),
+        }
+    }
+
+    /// Run `f` on the pool, returning a handle to its result
+    pub(crate) fn spawn<F, R>(&self, meta: Arc<Meta>, f: F) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let control = TaskControl::new();
+        let handle = JoinHandle::with_meta(control.clone(), meta.clone());
+
+        let id = meta.id;
+        let job: Job = Box::new(move || {
+            let result = task::with_id(id, || panic::catch_unwind(AssertUnwindSafe(f)))
+                .map_err(JoinError::panic);
+            control.complete(result);
+        });
+
+        if let Err(e) = self.submit(job) {
+            // Nothing will ever run the job; report it through the handle
+            return JoinHandle::failed(JoinError::new(e.to_string()), meta);
+        }
+
+        handle
+    }
+
+    /// Queue a job, waking or spawning a thread for it
+    pub(crate) fn submit(&self, job: Job) -> io::Result<()> {
+        let mut state = self.inner.state.lock().unwrap();
+
+        if state.shutdown {
+            return Err(io::Error::other("blocking pool is shut down"));
+        }
+
+        state.queue.push_back(job);
+
+        if state.num_idle > 0 {
+            state.num_idle -= 1;
+            state.num_notify += 1;
+            self.inner.condvar.notify_one();
+        } else if state.num_threads < self.inner.config.max_threads {
+            let id = state.next_id;
+            state.next_id += 1;
+
+            let inner = self.inner.clone();
+            let mut builder = thread::Builder::new()
+                .name(format!("{}-blocking-{}", self.inner.config.thread_name, id));
+
+            if let Some(stack_size) = self.inner.config.stack_size {
+                builder = builder.stack_size(stack_size);
+            }
+
+            match builder.spawn(move || inner.run(id)) {
+                Ok(handle) => {
+                    state.num_threads += 1;
+                    state.threads.push((id, handle));
+                }
+                // Busy threads will still pick the job up eventually
+                Err(e) if state.num_threads > 0 => drop(e),
+                Err(e) => {
+                    state.queue.pop_back();
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stop accepting work and wait for every pool thread to exit
+    ///
+    /// Jobs already queued still run. With a `deadline`, threads still busy
+    /// once it passes are detached and exit on their own when done.
+    pub(crate) fn shutdown(&self, deadline: Option<Instant>) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.shutdown = true;
+        self.inner.condvar.notify_all();
+
+        // Don't wait for ourselves when shut down from a pool thread
+        let current = thread::current().id();
+        let on_pool = state.threads.iter().any(|(_, handle)| handle.thread().id() == current);
+        let remaining = usize::from(on_pool);
+
+        while state.num_threads > remaining {
+            state = match deadline {
+                None => self.inner.condvar.wait(state).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    self.inner.condvar.wait_timeout(state, deadline - now).unwrap().0
+                }
+            };
+        }
+
+        let threads = std::mem::take(&mut state.threads);
+        drop(state);
+
+        for (_, handle) in threads {
+            if handle.thread().id() != current && (deadline.is_none() || handle.is_finished()) {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+impl Inner {
+    /// Blocking thread main loop
+    fn run(&self, id: usize) {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            if let Some(job) = state.queue.pop_front() {
+                drop(state);
+                job();
+                state = self.state.lock().unwrap();
+                continue;
+            }
+
+            if state.shutdown {
+                break;
+            }
+
+            state.num_idle += 1;
+            let (guard, timeout) = self
+                .condvar
+                .wait_timeout(state, self.config.keep_alive)
+                .unwrap();
+            state = guard;
+
+            if state.num_notify > 0 {
+                // A submitter already took us off the idle count
+                state.num_notify -= 1;
+            } else {
+                state.num_idle -= 1;
+
+                if timeout.timed_out() && state.queue.is_empty() && !state.shutdown {
+                    // Idle for too long; detach ourselves and exit
+                    state.threads.retain(|(thread_id, _)| *thread_id != id);
+                    break;
+                }
+            }
+        }
+
+        state.num_threads -= 1;
+        if state.shutdown {
+            // Let `shutdown` know another thread is gone
+            self.condvar.notify_all();
+        }
+    }
+}
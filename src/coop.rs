@@ -0,0 +1,86 @@
+//! Cooperative scheduling budget consumed by cycle's leaf futures
+
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Operations a task may perform per poll before it is forced to yield
+///
+/// Leaf futures in `net`, `time` and `sync` consume one unit per poll and
+/// return `Pending` once it is spent, even if they could make progress.
+const INITIAL_BUDGET: u8 = 128;
+
+thread_local! {
+    /// Budget of the task being polled on this thread; `None` is unconstrained
+    static CURRENT: Cell<Option<u8>> = const { Cell::new(None) };
+}
+
+pin_project_lite::pin_project! {
+    /// Future returned by [`unconstrained`]
+    #[must_use = "futures do nothing unless polled"]
+    pub struct Unconstrained<F> {
+        #[pin]
+        inner: F,
+    }
+}
+
+/// Run `f` with a fresh budget; called around every task poll
+pub(crate) fn budget<R>(f: impl FnOnce() -> R) -> R {
+    with_budget(Some(INITIAL_BUDGET), f)
+}
+
+fn with_budget<R>(budget: Option<u8>, f: impl FnOnce() -> R) -> R {
+    struct Reset(Option<u8>);
+
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            CURRENT.with(|current| current.set(self.0));
+        }
+    }
+
+    let prev = CURRENT.with(|current| current.replace(budget));
+    let _reset = Reset(prev);
+    f()
+}
+
+/// Consume one unit of budget, or wake the task and return `Pending` if it is spent
+pub(crate) fn poll_proceed(cx: &mut Context<'_>) -> Poll<()> {
+    CURRENT.with(|current| match current.get() {
+        None => Poll::Ready(()),
+        Some(0) => {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+        Some(remaining) => {
+            current.set(Some(remaining - 1));
+            Poll::Ready(())
+        }
+    })
+}
+
+/// Consume a unit of the current task's budget, yielding if it is spent
+///
+/// Call this in loops that do a lot of work without awaiting any of cycle's
+/// own resources, so they give other tasks a turn. Outside of a task this
+/// returns immediately.
+pub async fn consume_budget() {
+    std::future::poll_fn(poll_proceed).await
+}
+
+/// Poll `inner` without a budget, so it is never forced to yield
+///
+/// This opts the future out of cooperative scheduling; a loop over
+/// always-ready resources inside it can starve other tasks on its worker.
+pub fn unconstrained<F: Future>(inner: F) -> Unconstrained<F> {
+    Unconstrained { inner }
+}
+
+impl<F: Future> Future for Unconstrained<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let inner = self.project().inner;
+        with_budget(None, || inner.poll(cx))
+    }
+}
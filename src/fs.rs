@@ -5,7 +5,6 @@ use std::io::{self, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use futures_util::ready;
 
 /// Async file handle
 pub struct File {
@@ -1 +0,0 @@
-//! Filesystem operations (placeholder)
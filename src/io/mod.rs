@@ -3,7 +3,7 @@
 use std::io;
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use std::future::Future;
+use std::future::{poll_fn, Future};
 
 /// Async read trait
 pub trait AsyncRead {
@@ -33,30 +33,110 @@ pub trait AsyncWrite {
 /// Async read extension methods
 pub trait AsyncReadExt: AsyncRead {
     /// Read data
-    fn read(&mut self, _buf: &mut [u8]) -> impl Future<Output = io::Result<usize>> + '_
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> impl Future<Output = io::Result<usize>> + 'a
+    where
+        Self: Unpin,
+    {
+        poll_fn(move |cx| Pin::new(&mut *self).poll_read(cx, buf))
+    }
+    
+    /// Read until EOF, appending to `buf`
+    fn read_to_end<'a>(&'a mut self, buf: &'a mut Vec<u8>) -> impl Future<Output = io::Result<usize>> + 'a
+    where
+        Self: Unpin,
+    {
+        async move {
+            let start = buf.len();
+            let mut chunk = [0u8; 8192];
+            
+            loop {
+                match self.read(&mut chunk).await? {
+                    0 => return Ok(buf.len() - start),
+                    n => buf.extend_from_slice(&chunk[..n]),
+                }
+            }
+        }
+    }
+    
+    /// Read until EOF, appending UTF-8 data to `buf`
+    fn read_to_string<'a>(&'a mut self, buf: &'a mut String) -> impl Future<Output = io::Result<usize>> + 'a
     where
         Self: Unpin,
     {
         async move {
-            // TODO: Implement read method
-            todo!("AsyncReadExt::read not implemented")
+            let mut bytes = Vec::new();
+            let n = self.read_to_end(&mut bytes).await?;
+            let text = String::from_utf8(bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            buf.push_str(&text);
+            Ok(n)
         }
     }
 }
 
 /// Async write extension methods
 pub trait AsyncWriteExt: AsyncWrite {
+    /// Write data, returning how many bytes were written
+    fn write<'a>(&'a mut self, buf: &'a [u8]) -> impl Future<Output = io::Result<usize>> + 'a
+    where
+        Self: Unpin,
+    {
+        poll_fn(move |cx| Pin::new(&mut *self).poll_write(cx, buf))
+    }
+    
     /// Write all data
-    fn write_all(&mut self, _buf: &[u8]) -> impl Future<Output = io::Result<()>> + '_
+    fn write_all<'a>(&'a mut self, buf: &'a [u8]) -> impl Future<Output = io::Result<()>> + 'a
     where
         Self: Unpin,
     {
         async move {
-            // TODO: Implement write_all method
-            todo!("AsyncWriteExt::write_all not implemented")
+            let mut written = 0;
+            while written < buf.len() {
+                match self.write(&buf[written..]).await? {
+                    0 => return Err(io::ErrorKind::WriteZero.into()),
+                    n => written += n,
+                }
+            }
+            Ok(())
         }
     }
+    
+    /// Flush buffered data
+    fn flush(&mut self) -> impl Future<Output = io::Result<()>> + '_
+    where
+        Self: Unpin,
+    {
+        poll_fn(move |cx| Pin::new(&mut *self).poll_flush(cx))
+    }
+    
+    /// Shut down the write half
+    fn shutdown(&mut self) -> impl Future<Output = io::Result<()>> + '_
+    where
+        Self: Unpin,
+    {
+        poll_fn(move |cx| Pin::new(&mut *self).poll_shutdown(cx))
+    }
 }
 
 impl<T: AsyncRead> AsyncReadExt for T {}
 impl<T: AsyncWrite> AsyncWriteExt for T {}
+
+/// Copy all data from `reader` to `writer`, returning the number of bytes copied
+pub async fn copy<R, W>(reader: &mut R, writer: &mut W) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = [0u8; 8192];
+    let mut total = 0u64;
+    
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            writer.flush().await?;
+            return Ok(total);
+        }
+        writer.write_all(&buf[..n]).await?;
+        total += n as u64;
+    }
+}
@@ -11,6 +11,9 @@ pub mod scheduler;
 pub mod reactor;
 pub mod io;
 
+mod blocking;
+mod coop;
+
 #[cfg(feature = "net")]
 pub mod net;
 
@@ -20,28 +23,54 @@ pub mod time;
 #[cfg(feature = "sync")]
 pub mod sync;
 
+#[cfg(feature = "fs")]
+pub mod fs;
+
+#[cfg(feature = "macros")]
+pub mod macros;
+
 /// High-performance global runtime
 static GLOBAL_RUNTIME: once_cell::sync::Lazy<Arc<runtime::Runtime>> = 
-    once_cell::sync::Lazy::new(|| Arc::new(runtime::Runtime::new()));
+    once_cell::sync::Lazy::new(|| {
+        let runtime = runtime::Builder::new()
+            .enable_all()
+            .build()
+            .expect("Failed to build global runtime");
+        Arc::new(runtime)
+    });
 
-/// Spawn a task on the global CYCLE runtime
+/// Spawn a task on the current runtime, or the global CYCLE runtime outside of one
+#[track_caller]
 pub fn spawn<F>(future: F) -> task::JoinHandle<F::Output>
 where
     F: Future + Send + 'static,
     F::Output: Send + 'static,
 {
-    GLOBAL_RUNTIME.spawn(future)
+    spawn_with_priority(task::Priority::Normal, future)
 }
 
-/// Block on a future using the global runtime
-pub fn block_on<F>(future: F) -> F::Output
+/// Spawn a task with the given scheduling priority on the current runtime,
+/// or the global CYCLE runtime outside of one
+#[track_caller]
+pub fn spawn_with_priority<F>(priority: task::Priority, future: F) -> task::JoinHandle<F::Output>
 where
     F: Future + Send + 'static,
     F::Output: Send + 'static,
 {
+    let meta = task::Meta::new(None, std::panic::Location::caller());
+    runtime::handle::with_current(|handle| handle.spawn_task(meta, priority, future))
+}
+
+/// Run a future to completion on the calling thread using the global runtime
+pub fn block_on<F: Future>(future: F) -> F::Output {
     GLOBAL_RUNTIME.block_on(future)
 }
 
+pub use task::scope;
+
+#[cfg(feature = "macros")]
+pub use cycle_macros::{main, test};
+
 /// Get global runtime statistics
 pub fn stats() -> runtime::RuntimeStatsSnapshot {
     GLOBAL_RUNTIME.stats()
@@ -62,6 +91,9 @@ pub mod prelude {
     #[cfg(feature = "sync")]
     pub use crate::sync::{Mutex, RwLock};
     
+    #[cfg(feature = "macros")]
+    pub use crate::{join, select, try_join};
+    
     // Re-export standard time types
     pub use std::time::{Duration, Instant};
 }
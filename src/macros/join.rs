@@ -0,0 +1,142 @@
+//! The `join!` and `try_join!` macros
+
+/// Wait for several futures to complete, returning a tuple of their outputs
+///
+/// The futures run concurrently on the current task rather than being
+/// spawned, so they may borrow from the caller. Each poll starts with a
+/// different future so none of them is starved.
+///
+/// ```no_run
+/// use cycle::time::sleep;
+/// use std::time::Duration;
+///
+/// cycle::block_on(async {
+///     let (a, b) = cycle::join!(
+///         async { sleep(Duration::from_millis(10)).await; 1 },
+///         async { sleep(Duration::from_millis(20)).await; 2 },
+///     );
+///     assert_eq!(a + b, 3);
+/// });
+/// ```
+#[macro_export]
+macro_rules! join {
+    (@parse [$($futures:tt)*] [$($d:tt)*] $f:expr, $($rest:tt)*) => {
+        $crate::join!(@parse [$($futures)* [($($d)*) ($f)]] [$($d)* _] $($rest)*)
+    };
+    (@parse [$($futures:tt)*] [$($d:tt)*] $f:expr) => {
+        $crate::join!(@parse [$($futures)* [($($d)*) ($f)]] [$($d)* _])
+    };
+    (@parse [$( [($($d:tt)*) ($f:expr)] )+] [$($total:tt)*]) => {{
+        let mut __futures = $crate::__cycle_nest!($(
+            $crate::macros::support::pin!($crate::macros::support::MaybeDone::new(
+                $crate::macros::support::IntoFuture::into_future($f)
+            ))
+        ),+);
+        let __count = $crate::__cycle_count!($($total)*);
+        let mut __skip = 0;
+
+        $crate::macros::support::poll_fn(|cx| {
+            let start = __skip;
+            __skip = (__skip + 1) % __count;
+
+            let mut done = true;
+            for offset in 0..__count {
+                let index = (start + offset) % __count;
+                $(
+                    if index == $crate::__cycle_count!($($d)*) {
+                        done &= $crate::__cycle_field!(__futures; $($d)*).as_mut().poll_done(cx);
+                    }
+                )+
+            }
+
+            if !done {
+                return $crate::macros::support::Poll::Pending;
+            }
+
+            $crate::macros::support::Poll::Ready(($(
+                $crate::__cycle_field!(__futures; $($d)*).as_mut().take_output(),
+            )+))
+        })
+        .await
+    }};
+
+    () => { () };
+    ($($t:tt)*) => {
+        $crate::join!(@parse [] [] $($t)*)
+    };
+}
+
+/// Wait for several fallible futures, returning early if any of them fails
+///
+/// Like [`join!`](crate::join), but every future must resolve to a `Result`
+/// with the same error type. The first error is returned as soon as it
+/// occurs and the remaining futures are dropped; otherwise the values are
+/// returned as a tuple.
+///
+/// ```no_run
+/// use cycle::net::TcpStream;
+///
+/// cycle::block_on(async {
+///     let (a, b) = cycle::try_join!(
+///         TcpStream::connect("127.0.0.1:8080"),
+///         TcpStream::connect("127.0.0.1:8081"),
+///     )?;
+///     # drop((a, b));
+///     Ok::<(), std::io::Error>(())
+/// })?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[macro_export]
+macro_rules! try_join {
+    (@parse [$($futures:tt)*] [$($d:tt)*] $f:expr, $($rest:tt)*) => {
+        $crate::try_join!(@parse [$($futures)* [($($d)*) ($f)]] [$($d)* _] $($rest)*)
+    };
+    (@parse [$($futures:tt)*] [$($d:tt)*] $f:expr) => {
+        $crate::try_join!(@parse [$($futures)* [($($d)*) ($f)]] [$($d)* _])
+    };
+    (@parse [$( [($($d:tt)*) ($f:expr)] )+] [$($total:tt)*]) => {{
+        let mut __futures = $crate::__cycle_nest!($(
+            $crate::macros::support::pin!($crate::macros::support::MaybeDone::new(
+                $crate::macros::support::IntoFuture::into_future($f)
+            ))
+        ),+);
+        let __count = $crate::__cycle_count!($($total)*);
+        let mut __skip = 0;
+
+        $crate::macros::support::poll_fn(|cx| {
+            let start = __skip;
+            __skip = (__skip + 1) % __count;
+
+            let mut done = true;
+            for offset in 0..__count {
+                let index = (start + offset) % __count;
+                $(
+                    if index == $crate::__cycle_count!($($d)*) {
+                        let mut future = $crate::__cycle_field!(__futures; $($d)*).as_mut();
+                        if future.as_mut().poll_done(cx) {
+                            if let ::core::option::Option::Some(e) = future.take_err() {
+                                return $crate::macros::support::Poll::Ready(::core::result::Result::Err(e));
+                            }
+                        } else {
+                            done = false;
+                        }
+                    }
+                )+
+            }
+
+            if !done {
+                return $crate::macros::support::Poll::Pending;
+            }
+
+            $crate::macros::support::Poll::Ready(::core::result::Result::Ok(($(
+                $crate::__cycle_field!(__futures; $($d)*).as_mut().take_ok(),
+            )+)))
+        })
+        .await
+    }};
+
+    () => { ::core::result::Result::Ok(()) };
+    ($($t:tt)*) => {
+        $crate::try_join!(@parse [] [] $($t)*)
+    };
+}
@@ -1 +1,40 @@
-//! Procedural macros (placeholder)
+//! Macros for waiting on several futures at once
+//!
+//! [`select!`](crate::select) races futures and runs the handler of the first
+//! one to complete, [`join!`](crate::join) waits for all of them and
+//! [`try_join!`](crate::try_join) waits for all of them unless one fails.
+//! All three poll their futures from within the calling task, so they share
+//! its cooperative budget and dropping them cancels every future involved.
+
+mod join;
+mod select;
+
+#[doc(hidden)]
+pub mod support;
+
+pub use crate::{join, select, try_join};
+pub use cycle_macros::{main, test};
+
+/// Number of `_` tokens, used to give each branch an index
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cycle_count {
+    (@unit $d:tt) => { () };
+    ($($d:tt)*) => { <[()]>::len(&[$($crate::__cycle_count!(@unit $d)),*]) };
+}
+
+/// Nest a list of expressions as `(a, (b, (c, ())))`
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cycle_nest {
+    () => { () };
+    ($head:expr $(, $tail:expr)*) => { ($head, $crate::__cycle_nest!($($tail),*)) };
+}
+
+/// Field of a nested tuple at the depth given by the number of `_` tokens
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cycle_field {
+    ($base:expr; ) => { $base.0 };
+    ($base:expr; _ $($d:tt)*) => { $crate::__cycle_field!($base.1; $($d)*) };
+}
@@ -0,0 +1,184 @@
+//! The `select!` macro
+
+/// Wait on several futures at once, running the handler of the first to complete
+///
+/// Each branch has the form `<pattern> = <future> (, if <precondition>)? => <handler>`.
+/// On entry every precondition is evaluated, then every future expression.
+/// The enabled futures are polled from within the current task until one
+/// completes with a value matching its pattern; its handler then runs with
+/// the pattern's bindings and its value becomes the value of `select!`.
+/// All other futures are dropped, cancelling them.
+///
+/// - A branch whose precondition is `false` is disabled: its future is still
+///   created but never polled.
+/// - A branch whose output doesn't match its pattern is disabled and the
+///   remaining branches keep going. The check is done through a reference,
+///   so patterns with `mut` bindings only work for `Copy` values.
+/// - Once every branch is disabled the `else` handler runs; without an
+///   `else` branch `select!` panics.
+/// - Branches are polled starting from a random one so none is starved.
+///   Writing `biased;` before the first branch polls them in order instead.
+///
+/// Every poll also consumes a unit of the task's cooperative budget, so a
+/// `select!` loop over always-ready futures still yields to other tasks.
+/// At most 64 branches are supported.
+///
+/// ```no_run
+/// use cycle::time::sleep;
+/// use std::time::Duration;
+///
+/// cycle::block_on(async {
+///     let (tx, mut rx) = futures::channel::mpsc::unbounded::<u32>();
+///     let mut closed = false;
+///
+///     loop {
+///         cycle::select! {
+///             biased;
+///
+///             Some(value) = futures::StreamExt::next(&mut rx), if !closed => {
+///                 println!("received {}", value);
+///             }
+///             _ = sleep(Duration::from_secs(1)) => {
+///                 println!("no message within a second");
+///                 closed = true;
+///             }
+///             else => break,
+///         }
+///     }
+///     drop(tx);
+/// });
+/// ```
+#[macro_export]
+macro_rules! select {
+    // `else` must be the last branch
+    (@parse [$bias:tt] [$($branches:tt)*] [$($d:tt)*] else => { $($h:tt)* } $(,)?) => {
+        $crate::select!(@gen [$bias] [$($branches)*] [$($d)*] [{ $($h)* }])
+    };
+    (@parse [$bias:tt] [$($branches:tt)*] [$($d:tt)*] else => $h:expr $(,)?) => {
+        $crate::select!(@gen [$bias] [$($branches)*] [$($d)*] [$h])
+    };
+
+    // Branches with a precondition
+    (@parse [$bias:tt] [$($branches:tt)*] [$($d:tt)*] $p:pat = $f:expr, if $c:expr => { $($h:tt)* }, $($rest:tt)*) => {
+        $crate::select!(@parse [$bias] [$($branches)* [($($d)*) ($p) ($f) ($c) ({ $($h)* })]] [$($d)* _] $($rest)*)
+    };
+    (@parse [$bias:tt] [$($branches:tt)*] [$($d:tt)*] $p:pat = $f:expr, if $c:expr => { $($h:tt)* } $($rest:tt)*) => {
+        $crate::select!(@parse [$bias] [$($branches)* [($($d)*) ($p) ($f) ($c) ({ $($h)* })]] [$($d)* _] $($rest)*)
+    };
+    (@parse [$bias:tt] [$($branches:tt)*] [$($d:tt)*] $p:pat = $f:expr, if $c:expr => $h:expr, $($rest:tt)*) => {
+        $crate::select!(@parse [$bias] [$($branches)* [($($d)*) ($p) ($f) ($c) ($h)]] [$($d)* _] $($rest)*)
+    };
+    (@parse [$bias:tt] [$($branches:tt)*] [$($d:tt)*] $p:pat = $f:expr, if $c:expr => $h:expr) => {
+        $crate::select!(@parse [$bias] [$($branches)* [($($d)*) ($p) ($f) ($c) ($h)]] [$($d)* _])
+    };
+
+    // Branches without one
+    (@parse [$bias:tt] [$($branches:tt)*] [$($d:tt)*] $p:pat = $f:expr => { $($h:tt)* }, $($rest:tt)*) => {
+        $crate::select!(@parse [$bias] [$($branches)* [($($d)*) ($p) ($f) (true) ({ $($h)* })]] [$($d)* _] $($rest)*)
+    };
+    (@parse [$bias:tt] [$($branches:tt)*] [$($d:tt)*] $p:pat = $f:expr => { $($h:tt)* } $($rest:tt)*) => {
+        $crate::select!(@parse [$bias] [$($branches)* [($($d)*) ($p) ($f) (true) ({ $($h)* })]] [$($d)* _] $($rest)*)
+    };
+    (@parse [$bias:tt] [$($branches:tt)*] [$($d:tt)*] $p:pat = $f:expr => $h:expr, $($rest:tt)*) => {
+        $crate::select!(@parse [$bias] [$($branches)* [($($d)*) ($p) ($f) (true) ($h)]] [$($d)* _] $($rest)*)
+    };
+    (@parse [$bias:tt] [$($branches:tt)*] [$($d:tt)*] $p:pat = $f:expr => $h:expr) => {
+        $crate::select!(@parse [$bias] [$($branches)* [($($d)*) ($p) ($f) (true) ($h)]] [$($d)* _])
+    };
+
+    (@parse [$bias:tt] [] []) => {
+        ::core::compile_error!("`select!` needs at least one branch")
+    };
+
+    // No `else` branch
+    (@parse [$bias:tt] [$($branches:tt)*] [$($d:tt)*]) => {
+        $crate::select!(@gen [$bias] [$($branches)*] [$($d)*] [
+            ::core::panic!("all branches of `select!` are disabled and there is no `else` branch")
+        ])
+    };
+
+    (@start biased, $count:expr) => { 0 };
+    (@start fair, $count:expr) => { $crate::macros::support::start_index($count) };
+
+    (@gen [$bias:tt] [$( [($($d:tt)*) ($p:pat) ($f:expr) ($c:expr) ($($h:tt)*)] )+] [$($total:tt)*] [$($else:tt)*]) => {{
+        const _: () = ::core::assert!(
+            $crate::__cycle_count!($($total)*) <= 64,
+            "`select!` supports at most 64 branches",
+        );
+        let __count = $crate::__cycle_count!($($total)*);
+
+        let mut __disabled: u64 = 0;
+        $(
+            if !$c {
+                __disabled |= 1 << $crate::__cycle_count!($($d)*);
+            }
+        )+
+
+        let mut __futures = $crate::__cycle_nest!($(
+            $crate::macros::support::pin!($crate::macros::support::IntoFuture::into_future($f))
+        ),+);
+
+        let __output = $crate::macros::support::poll_fn(|cx| {
+            if $crate::macros::support::poll_budget(cx).is_pending() {
+                return $crate::macros::support::Poll::Pending;
+            }
+
+            let start = $crate::select!(@start $bias, __count);
+            for offset in 0..__count {
+                let branch = (start + offset) % __count;
+                $(
+                    if branch == $crate::__cycle_count!($($d)*) && __disabled & (1 << branch) == 0 {
+                        let future = $crate::__cycle_field!(__futures; $($d)*).as_mut();
+                        if let $crate::macros::support::Poll::Ready(out) =
+                            $crate::macros::support::Future::poll(future, cx)
+                        {
+                            #[allow(unused_variables, unreachable_patterns, clippy::redundant_pattern_matching)]
+                            let matched = ::core::matches!(&out, $p);
+                            if matched {
+                                return $crate::macros::support::Poll::Ready(
+                                    $crate::select!(@wrap (out) $($d)*)
+                                );
+                            }
+                            __disabled |= 1 << branch;
+                        }
+                    }
+                )+
+            }
+
+            if __disabled == !0u64 >> (64 - __count) {
+                $crate::macros::support::Poll::Ready(
+                    $crate::select!(@wrap_last ($crate::macros::support::Disabled) $($total)*)
+                )
+            } else {
+                $crate::macros::support::Poll::Pending
+            }
+        })
+        .await;
+
+        match __output {
+            $(
+                $crate::select!(@wrap ($p) $($d)*) => $($h)*,
+            )+
+            $crate::select!(@wrap_last ($crate::macros::support::Disabled) $($total)*) => $($else)*,
+            #[allow(unreachable_patterns)]
+            _ => ::core::unreachable!("`select!` output did not match its branch"),
+        }
+    }};
+
+    // Wrap the output of the branch at the given depth; also used for patterns
+    (@wrap $x:tt) => { $crate::macros::support::Branch::This($x) };
+    (@wrap $x:tt _ $($d:tt)*) => {
+        $crate::macros::support::Branch::Next($crate::select!(@wrap $x $($d)*))
+    };
+    (@wrap_last $x:tt) => { $x };
+    (@wrap_last $x:tt _ $($d:tt)*) => {
+        $crate::macros::support::Branch::Next($crate::select!(@wrap_last $x $($d)*))
+    };
+
+    (biased; $($t:tt)*) => {
+        $crate::select!(@parse [biased] [] [] $($t)*)
+    };
+    ($($t:tt)*) => {
+        $crate::select!(@parse [fair] [] [] $($t)*)
+    };
+}
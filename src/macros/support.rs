@@ -0,0 +1,89 @@
+//! Items used by the expansions of cycle's macros; not public API
+
+pub use std::future::{poll_fn, Future, IntoFuture};
+pub use std::pin::{pin, Pin};
+pub use std::task::Poll;
+
+use std::task::Context;
+
+/// Output of a `select!` branch, nested once per preceding branch
+pub enum Branch<A, B> {
+    This(A),
+    Next(B),
+}
+
+/// Output of a `select!` whose branches are all disabled
+pub struct Disabled;
+
+pin_project_lite::pin_project! {
+    /// A future that holds on to its output once complete, for `join!`
+    pub struct MaybeDone<F: Future> {
+        #[pin]
+        future: Option<F>,
+        output: Option<F::Output>,
+    }
+}
+
+/// Consume a unit of the task's budget before polling the branches of a `select!`
+pub fn poll_budget(cx: &mut Context<'_>) -> Poll<()> {
+    crate::coop::poll_proceed(cx)
+}
+
+/// Random branch to start polling from, so no branch is favoured
+pub fn start_index(branches: usize) -> usize {
+    fastrand::usize(..branches)
+}
+
+impl<F: Future> MaybeDone<F> {
+    pub fn new(future: F) -> Self {
+        Self {
+            future: Some(future),
+            output: None,
+        }
+    }
+
+    /// Poll the future unless it already completed, returning whether it has
+    pub fn poll_done(self: Pin<&mut Self>, cx: &mut Context<'_>) -> bool {
+        let mut this = self.project();
+        if let Some(future) = this.future.as_mut().as_pin_mut() {
+            match future.poll(cx) {
+                Poll::Ready(output) => {
+                    *this.output = Some(output);
+                    this.future.set(None);
+                }
+                Poll::Pending => return false,
+            }
+        }
+        true
+    }
+
+    /// Take the output of the completed future
+    pub fn take_output(self: Pin<&mut Self>) -> F::Output {
+        self.project()
+            .output
+            .take()
+            .expect("`MaybeDone` output taken before the future completed")
+    }
+}
+
+impl<T, E, F: Future<Output = Result<T, E>>> MaybeDone<F> {
+    /// Take the output of the completed future if it failed
+    pub fn take_err(self: Pin<&mut Self>) -> Option<E> {
+        let output = self.project().output;
+        match output.take() {
+            Some(Err(e)) => Some(e),
+            ok => {
+                *output = ok;
+                None
+            }
+        }
+    }
+
+    /// Take the value of the completed future, which must have succeeded
+    pub fn take_ok(self: Pin<&mut Self>) -> T {
+        match self.take_output() {
+            Ok(value) => value,
+            Err(_) => unreachable!("`try_join!` returns on the first error"),
+        }
+    }
+}
@@ -1,32 +1,36 @@
 //! High-performance networking with real async I/O
 
 use crate::io::{AsyncRead, AsyncWrite};
-use crate::reactor::Reactor;
+use crate::reactor::{Direction, Reactor, ScheduledIo};
 use std::io::{self, ErrorKind};
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
 use mio::net::{TcpListener as MioTcpListener, TcpStream as MioTcpStream, UdpSocket as MioUdpSocket};
-use mio::{Interest, Token};
+use mio::Interest;
 
 /// High-performance TCP listener
+///
+/// Sockets are bound to the reactor of the runtime they were created on.
 pub struct TcpListener {
     inner: MioTcpListener,
-    token: Token,
+    reactor: Arc<Reactor>,
+    io: Arc<ScheduledIo>,
 }
 
 /// High-performance TCP stream
 pub struct TcpStream {
     inner: MioTcpStream,
-    token: Token,
-    read_ready: bool,
-    write_ready: bool,
+    reactor: Arc<Reactor>,
+    io: Arc<ScheduledIo>,
 }
 
 /// High-performance UDP socket
 pub struct UdpSocket {
     inner: MioUdpSocket,
-    token: Token,
+    reactor: Arc<Reactor>,
+    io: Arc<ScheduledIo>,
 }
 
 impl TcpListener {
@@ -51,43 +55,47 @@ impl TcpListener {
         
         let std_listener: std::net::TcpListener = socket.into();
         let mut listener = MioTcpListener::from_std(std_listener);
-        let token = Reactor::with(|reactor| reactor.register(&mut listener, Interest::READABLE))?;
+        let reactor = Reactor::current()?;
+        let io = reactor.register(&mut listener, Interest::READABLE)?;
         Ok(Self {
             inner: listener,
-            token,
+            reactor,
+            io,
         })
     }
     
     /// Accept a new connection with proper async handling
     pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        crate::coop::consume_budget().await;
+        
         loop {
+            let event = self.io.readiness(Direction::Read).await?;
+            
             match self.inner.accept() {
                 Ok((mut stream, addr)) => {
-                    let token = Reactor::with(|reactor| {
-                        reactor.register(&mut stream, Interest::READABLE | Interest::WRITABLE)
-                    })?;
+                    // Accepted streams share the listener's reactor
+                    let io = self.reactor.register(&mut stream, Interest::READABLE | Interest::WRITABLE)?;
                     
                     return Ok((TcpStream {
                         inner: stream,
-                        token,
-                        read_ready: false,
-                        write_ready: false,
+                        reactor: self.reactor.clone(),
+                        io,
                     }, addr));
                 }
-                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
-                    // Wait for readiness
-                    let token = self.token;
-                    let fut = Reactor::with(move |_reactor| {
-                        Reactor::wait_for_io(token)
-                    });
-                    fut.await?;
-                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => self.io.clear_readiness(event),
                 Err(e) => return Err(e),
             }
         }
     }
 }
 
+impl TcpListener {
+    /// Get the address the listener is bound to
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
 impl TcpStream {
     /// Connect to an address with real async I/O
     pub async fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
@@ -108,28 +116,35 @@ impl TcpStream {
         // Start connection
         match socket.connect(&addr.into()) {
             Ok(()) => {}
-            Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::NotConnected => {}
+            Err(ref e) if connect_in_progress(e) => {}
             Err(e) => return Err(e),
         }
         
         let socket_std = socket.into();
         let mut stream = MioTcpStream::from_std(socket_std);
-        let token = Reactor::with(|reactor| {
-            reactor.register(&mut stream, Interest::READABLE | Interest::WRITABLE)
-        })?;
-        // Wait for connection to complete
-        let fut = Reactor::with(|_reactor| {
-            Reactor::wait_for_io(token)
-        });
-        fut.await?;
-        Reactor::wait_for_io(token).await?;
+        let reactor = Reactor::current()?;
+        let io = reactor.register(&mut stream, Interest::READABLE | Interest::WRITABLE)?;
         
-        Ok(Self {
+        // Built before waiting so a failed or cancelled connect deregisters on drop
+        let stream = Self {
             inner: stream,
-            token,
-            read_ready: false,
-            write_ready: false,
-        })
+            reactor,
+            io,
+        };
+        
+        // The socket turns writable once the connection completes or fails
+        loop {
+            let event = stream.io.readiness(Direction::Write).await?;
+            if let Some(e) = stream.inner.take_error()? {
+                return Err(e);
+            }
+            
+            match stream.inner.peer_addr() {
+                Ok(_) => return Ok(stream),
+                Err(ref e) if e.kind() == ErrorKind::NotConnected => stream.io.clear_readiness(event),
+                Err(e) => return Err(e),
+            }
+        }
     }
     /// Get local address
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
@@ -149,23 +164,19 @@ impl TcpStream {
 
 impl AsyncRead for TcpStream {
     fn poll_read(
-        mut self: Pin<&mut Self>,
+        self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<io::Result<usize>> {
+        ready!(crate::coop::poll_proceed(cx));
+        
+        let this = self.get_mut();
         loop {
-            match self.inner.read(buf) {
-                Ok(n) => return Poll::Ready(Ok(n)),
-                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
-                    if !self.read_ready {
-                        // Register for readiness notification
-                        Reactor::with(|reactor| reactor.register_waker(self.token, cx.waker().clone()));
-                        self.read_ready = false;
-                        return Poll::Pending;
-                    }
-                    self.read_ready = false;
-                }
-                Err(e) => return Poll::Ready(Err(e)),
+            let event = ready!(this.io.poll_ready(Direction::Read, cx))?;
+            
+            match this.inner.read(buf) {
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => this.io.clear_readiness(event),
+                result => return Poll::Ready(result),
             }
         }
     }
@@ -173,21 +184,19 @@ impl AsyncRead for TcpStream {
 
 impl AsyncWrite for TcpStream {
     fn poll_write(
-        mut self: Pin<&mut Self>,
+        self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<io::Result<usize>> {
+        ready!(crate::coop::poll_proceed(cx));
+        
+        let this = self.get_mut();
         loop {
-            match self.inner.write(buf) {
-                Ok(n) => return Poll::Ready(Ok(n)),
-                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
-                    if !self.write_ready {
-                        Reactor::with(|reactor| reactor.register_waker(self.token, cx.waker().clone()));
-                        return Poll::Pending;
-                    }
-                    self.write_ready = false;
-                }
-                Err(e) => return Poll::Ready(Err(e)),
+            let event = ready!(this.io.poll_ready(Direction::Write, cx))?;
+            
+            match this.inner.write(buf) {
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => this.io.clear_readiness(event),
+                result => return Poll::Ready(result),
             }
         }
     }
@@ -222,43 +231,78 @@ impl UdpSocket {
         socket.bind(&addr.into())?;
         
         let mut socket = MioUdpSocket::from_std(socket.into());
-        let token = Reactor::with(|reactor| {
-            reactor.register(&mut socket, Interest::READABLE | Interest::WRITABLE)
-        })?;
+        let reactor = Reactor::current()?;
+        let io = reactor.register(&mut socket, Interest::READABLE | Interest::WRITABLE)?;
         
         Ok(Self {
             inner: socket,
-            token,
+            reactor,
+            io,
         })
     }
     
+    /// Get the address the socket is bound to
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+    
     /// Send data to a specific address
     pub async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        crate::coop::consume_budget().await;
+        
         loop {
+            let event = self.io.readiness(Direction::Write).await?;
+            
             match self.inner.send_to(buf, addr) {
-                Ok(n) => return Ok(n),
-                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
-                    Reactor::wait_for_io(self.token).await?;
-                }
-                Err(e) => return Err(e),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => self.io.clear_readiness(event),
+                result => return result,
             }
         }
     }
     
     /// Receive data from any address
     pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        crate::coop::consume_budget().await;
+        
         loop {
+            let event = self.io.readiness(Direction::Read).await?;
+            
             match self.inner.recv_from(buf) {
-                Ok((n, addr)) => return Ok((n, addr)),
-                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
-                    Reactor::wait_for_io(self.token).await?;
-                }
-                Err(e) => return Err(e),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => self.io.clear_readiness(event),
+                result => return result,
             }
         }
     }
 }
 
+/// Whether a non-blocking `connect` failed only because it hasn't completed yet
+fn connect_in_progress(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    if e.raw_os_error() == Some(libc::EINPROGRESS) {
+        return true;
+    }
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::NotConnected)
+}
+
+// Deregister on drop so the reactor releases the token and the waiters
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        let _ = self.reactor.deregister(&mut self.inner, &self.io);
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        let _ = self.reactor.deregister(&mut self.inner, &self.io);
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        let _ = self.reactor.deregister(&mut self.inner, &self.io);
+    }
+}
+
 // Implement std::io traits for TcpStream
 use std::io::{Read, Write};
 
@@ -1,63 +1,99 @@
 //! I/O reactor for event-driven networking
 
-use mio::{Events, Poll, Registry, Token, Interest};
-use std::collections::HashMap;
+use mio::{Events, Poll, Registry, Token, Interest, Waker};
 use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::task::Waker as TaskWaker;
-use std::thread;
 use std::time::Duration;
-use once_cell::sync::Lazy;
 
-/// Global reactor instance
-pub static REACTOR: Lazy<Reactor> = Lazy::new(|| {
-    Reactor::new().expect("Failed to create reactor")
-});
+mod scheduled_io;
+mod slab;
+
+pub use scheduled_io::ScheduledIo;
+#[cfg(feature = "net")]
+pub(crate) use scheduled_io::Direction;
+use slab::Slab;
+
+/// Token of the waker that interrupts a blocked `turn`; the slab never hands it out
+const WAKE_TOKEN: Token = Token(usize::MAX);
 
 /// I/O reactor for managing async I/O events
+///
+/// Every runtime with I/O enabled owns one. It has no thread of its own:
+/// the runtime's idle workers take turns blocking on it, and once the
+/// runtime shuts down registering or waiting on it fails.
+///
+/// Tokens are recycled once their source is deregistered; the slab's
+/// generation bits keep a late event from reaching the new owner.
 pub struct Reactor {
-    registry: Arc<Registry>,
-    wakers: Arc<Mutex<HashMap<Token, TaskWaker>>>,
-    next_token: std::sync::atomic::AtomicUsize,
-    shutdown: Arc<std::sync::atomic::AtomicBool>,
+    registry: Registry,
+    sources: Mutex<Slab<Arc<ScheduledIo>>>,
+    shutdown: AtomicBool,
+    
+    /// Interrupts whoever is blocked in `turn`
+    waker: Waker,
+    
+    /// Locked by the thread turning the reactor
+    poller: Mutex<Poller>,
+}
+
+/// `Poll` and the buffer its events are read into
+struct Poller {
+    poll: Poll,
+    events: Events,
 }
 
 impl Reactor {
     /// Create a new reactor
-    pub fn new() -> io::Result<Self> {
+    pub(crate) fn new() -> io::Result<Self> {
         let poll = Poll::new()?;
-        let registry = Arc::new(poll.registry().try_clone()?);
-        let wakers = Arc::new(Mutex::new(HashMap::new()));
-        let next_token = std::sync::atomic::AtomicUsize::new(1);
-        let shutdown = Arc::new(std::sync::atomic::AtomicBool::new(false));
-        
-        // Start reactor thread
-        let poll_clone = poll;
-        let wakers_clone = wakers.clone();
-        let shutdown_clone = shutdown.clone();
-        
-        thread::spawn(move || {
-            Self::run_event_loop(poll_clone, wakers_clone, shutdown_clone);
-        });
+        let registry = poll.registry().try_clone()?;
+        let waker = Waker::new(&registry, WAKE_TOKEN)?;
         
         Ok(Self {
             registry,
-            wakers,
-            next_token,
-            shutdown,
+            sources: Mutex::new(Slab::new()),
+            shutdown: AtomicBool::new(false),
+            waker,
+            poller: Mutex::new(Poller {
+                poll,
+                events: Events::with_capacity(1024),
+            }),
         })
     }
     
-    /// Register an I/O source
-    pub fn register<S>(&self, source: &mut S, interest: Interest) -> io::Result<Token>
+    /// Reactor of the current runtime, or of the global runtime outside of one
+    pub(crate) fn current() -> io::Result<Arc<Reactor>> {
+        crate::runtime::handle::with_current(|handle| handle.reactor().cloned())
+    }
+    
+    fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::Acquire)
+    }
+    
+    /// Register an I/O source, returning the readiness state its events are recorded in
+    pub fn register<S>(&self, source: &mut S, interest: Interest) -> io::Result<Arc<ScheduledIo>>
     where
         S: mio::event::Source + ?Sized,
     {
-        let token_value = self.next_token.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-        let token = Token(token_value);
+        let io = {
+            // Checked under the lock so `shutdown` can't miss the new source
+            let mut sources = self.sources.lock().unwrap();
+            if self.is_shutdown() {
+                return Err(shutdown_error());
+            }
+            let token = sources.vacant_token()
+                .ok_or_else(|| io::Error::other("too many I/O sources registered with the reactor"))?;
+            let io = Arc::new(ScheduledIo::new(token));
+            sources.insert(io.clone());
+            io
+        };
         
-        self.registry.register(source, token, interest)?;
-        Ok(token)
+        if let Err(e) = self.registry.register(source, io.token(), interest) {
+            self.sources.lock().unwrap().remove(io.token());
+            return Err(e);
+        }
+        Ok(io)
     }
     
     /// Reregister an I/O source
@@ -68,86 +104,85 @@ impl Reactor {
         self.registry.reregister(source, token, interest)
     }
     
-    /// Deregister an I/O source
-    pub fn deregister<S>(&self, source: &mut S) -> io::Result<()>
+    /// Deregister an I/O source, releasing its token and dropping its waiters
+    ///
+    /// Events already in flight for the source are ignored, even once its
+    /// token is handed to another registration.
+    pub fn deregister<S>(&self, source: &mut S, io: &ScheduledIo) -> io::Result<()>
     where
         S: mio::event::Source + ?Sized,
     {
+        self.sources.lock().unwrap().remove(io.token());
+        io.clear_wakers();
         self.registry.deregister(source)
     }
     
-    /// Register a task waker for I/O readiness
-    pub fn register_waker(&self, token: Token, waker: TaskWaker) {
-        self.wakers.lock().unwrap().insert(token, waker);
-    }
-
-    /// Wait for I/O readiness
-    pub fn wait_for_io(token: Token) -> impl std::future::Future<Output = io::Result<()>> {
-        struct IoFuture {
-            token: Token,
-            registered: bool,
+    /// Wait up to `timeout` for I/O events, or until `wake`, and record the
+    /// readiness they carry
+    pub(crate) fn turn(&self, timeout: Option<Duration>) {
+        let mut poller = self.poller.lock().unwrap();
+        let Poller { poll, events } = &mut *poller;
+        if poll.poll(events, timeout).is_err() {
+            return;
         }
         
-        impl std::future::Future for IoFuture {
-            type Output = io::Result<()>;
-            
-            fn poll(
-                mut self: std::pin::Pin<&mut Self>,
-                cx: &mut std::task::Context<'_>,
-            ) -> std::task::Poll<Self::Output> {
-                if !self.registered {
-                    REACTOR.register_waker(self.token, cx.waker().clone());
-                    self.registered = true;
-                }
-                std::task::Poll::Pending
-            }
-        }
+        // Look the sources up first: waking a task may drop a socket, which
+        // needs the lock to deregister
+        let ready: Vec<_> = {
+            let sources = self.sources.lock().unwrap();
+            events
+                .iter()
+                .filter_map(|event| sources.get(event.token()).map(|io| (io.clone(), event)))
+                .collect()
+        };
         
-        IoFuture {
-            token,
-            registered: false,
+        for (io, event) in ready {
+            io.set_readiness(event);
         }
     }
     
-    /// Run the event loop
-    fn run_event_loop(
-        mut poll: Poll,
-        wakers: Arc<Mutex<HashMap<Token, TaskWaker>>>,
-        shutdown: Arc<std::sync::atomic::AtomicBool>,
-    ) {
-        let mut events = Events::with_capacity(1024);
-        
-        while !shutdown.load(std::sync::atomic::Ordering::Acquire) {
-            // Poll for events with timeout
-            if let Err(_) = poll.poll(&mut events, Some(Duration::from_millis(10))) {
-                continue;
-            }
-            
-            // Process events
-            for event in events.iter() {
-                let token = event.token();
-                
-                // Wake the associated task
-                if let Some(waker) = wakers.lock().unwrap().remove(&token) {
-                    waker.wake();
-                }
-            }
-        }
+    /// Number of sources currently registered
+    pub(crate) fn num_sources(&self) -> usize {
+        self.sources.lock().unwrap().len()
+    }
+    
+    /// Interrupt the thread blocked in `turn`, or make the next one return at once
+    pub(crate) fn wake(&self) {
+        let _ = self.waker.wake();
     }
     
-    /// Shutdown the reactor
+    /// Fail every pending and future wait
     pub fn shutdown(&self) {
-        self.shutdown.store(true, std::sync::atomic::Ordering::Release);
+        let sources = {
+            let mut sources = self.sources.lock().unwrap();
+            self.shutdown.store(true, Ordering::Release);
+            sources.drain()
+        };
+        for io in sources {
+            io.shutdown();
+        }
     }
 }
 
 /// Extension method for reactor access
 impl Reactor {
-    /// Access reactor in a thread-local context
+    /// Access the reactor of the current runtime, or of the global runtime
+    /// outside of one
+    ///
+    /// # Panics
+    ///
+    /// Panics if the current runtime was built without I/O enabled.
     pub fn with<F, R>(f: F) -> R
     where
         F: FnOnce(&Reactor) -> R,
     {
-        f(&*REACTOR)
+        match Self::current() {
+            Ok(reactor) => f(&reactor),
+            Err(e) => panic!("{}", e),
+        }
     }
 }
+
+pub(crate) fn shutdown_error() -> io::Error {
+    io::Error::other("the runtime's I/O driver has shut down")
+}
@@ -0,0 +1,227 @@
+//! Per-source readiness state shared between the reactor and the tasks doing I/O
+
+use mio::event::Event;
+use mio::Token;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+
+/// Source can be read from without blocking
+const READABLE: usize = 0b0_0001;
+
+/// Source can be written to without blocking
+const WRITABLE: usize = 0b0_0010;
+
+/// Read half closed by the peer; reads return EOF
+const READ_CLOSED: usize = 0b0_0100;
+
+/// Write half closed; writes fail
+const WRITE_CLOSED: usize = 0b0_1000;
+
+/// Source reported an error
+const ERROR: usize = 0b1_0000;
+
+/// Reactor shut down; waiting fails from now on
+const SHUTDOWN: usize = 0b10_0000;
+
+/// Readiness bits live below the tick
+const READINESS_MASK: usize = (1 << TICK_SHIFT) - 1;
+
+/// The tick counts events delivered to the source
+const TICK_SHIFT: u32 = 8;
+
+/// Which half of a source a task is waiting on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Read,
+    Write,
+}
+
+/// Readiness observed by `poll_ready`, handed back to `clear_readiness`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ReadyEvent {
+    ready: usize,
+    tick: usize,
+}
+
+/// Readiness of a registered I/O source
+///
+/// The reactor records every event in a set of readiness bits instead of
+/// only waking whoever happens to be waiting, so an event that arrives
+/// before a task starts waiting isn't lost. Readers and writers wait
+/// separately, and any number of tasks may wait in each direction, e.g.
+/// several tasks accepting on one listener.
+///
+/// Readiness is only cleared once an operation fails with `WouldBlock`, and
+/// only if no event arrived since the readiness was observed: every event
+/// bumps a tick, and a stale tick leaves the bits alone.
+pub struct ScheduledIo {
+    token: Token,
+
+    /// Readiness bits, with the tick in the bits above them
+    readiness: AtomicUsize,
+
+    waiters: Mutex<Waiters>,
+}
+
+/// Tasks waiting for a source to become ready, all woken together
+#[derive(Default)]
+struct Waiters {
+    readers: Vec<Waker>,
+    writers: Vec<Waker>,
+}
+
+impl Direction {
+    /// Readiness bits that let an operation in this direction proceed
+    fn mask(self) -> usize {
+        match self {
+            Direction::Read => READABLE | READ_CLOSED | ERROR | SHUTDOWN,
+            Direction::Write => WRITABLE | WRITE_CLOSED | ERROR | SHUTDOWN,
+        }
+    }
+}
+
+impl ScheduledIo {
+    pub(crate) fn new(token: Token) -> Self {
+        Self {
+            token,
+            readiness: AtomicUsize::new(0),
+            waiters: Mutex::new(Waiters::default()),
+        }
+    }
+
+    /// Token the source is registered with
+    pub fn token(&self) -> Token {
+        self.token
+    }
+
+    /// Record an event from the reactor and wake the tasks it unblocks
+    pub(crate) fn set_readiness(&self, event: &Event) {
+        let mut ready = 0;
+        if event.is_readable() {
+            ready |= READABLE;
+        }
+        if event.is_writable() {
+            ready |= WRITABLE;
+        }
+        if event.is_read_closed() {
+            ready |= READ_CLOSED;
+        }
+        if event.is_write_closed() {
+            ready |= WRITE_CLOSED;
+        }
+        if event.is_error() {
+            ready |= ERROR;
+        }
+        self.add_readiness(ready);
+    }
+
+    /// Fail every current and future wait, as the reactor has shut down
+    pub(crate) fn shutdown(&self) {
+        self.add_readiness(SHUTDOWN);
+    }
+
+    fn add_readiness(&self, ready: usize) {
+        let _ = self.readiness.fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+            let tick = (current >> TICK_SHIFT).wrapping_add(1);
+            Some((tick << TICK_SHIFT) | (current & READINESS_MASK) | ready)
+        });
+
+        // Waiters register under this lock before re-checking the bits, so
+        // taking it after the update can't miss one
+        let (readers, writers) = {
+            let mut waiters = self.waiters.lock().unwrap();
+            let readers = match ready & Direction::Read.mask() {
+                0 => Vec::new(),
+                _ => std::mem::take(&mut waiters.readers),
+            };
+            let writers = match ready & Direction::Write.mask() {
+                0 => Vec::new(),
+                _ => std::mem::take(&mut waiters.writers),
+            };
+            (readers, writers)
+        };
+
+        for waker in readers.into_iter().chain(writers) {
+            waker.wake();
+        }
+    }
+
+    /// Drop the wakers of any waiting tasks without waking them
+    pub(crate) fn clear_wakers(&self) {
+        let waiters = std::mem::take(&mut *self.waiters.lock().unwrap());
+        drop(waiters);
+    }
+
+    /// Poll for readiness in `direction`, registering `cx` if there is none
+    ///
+    /// Fails once the reactor has shut down.
+    pub(crate) fn poll_ready(&self, direction: Direction, cx: &mut Context<'_>) -> Poll<io::Result<ReadyEvent>> {
+        if let Some(event) = self.ready_event(direction) {
+            return Poll::Ready(event);
+        }
+
+        let mut waiters = self.waiters.lock().unwrap();
+        let list = match direction {
+            Direction::Read => &mut waiters.readers,
+            Direction::Write => &mut waiters.writers,
+        };
+        if !list.iter().any(|waker| waker.will_wake(cx.waker())) {
+            list.push(cx.waker().clone());
+        }
+
+        // An event may have landed before we registered
+        match self.ready_event(direction) {
+            Some(event) => {
+                drop(waiters);
+                Poll::Ready(event)
+            }
+            None => Poll::Pending,
+        }
+    }
+
+    /// Wait for readiness in `direction`
+    pub(crate) async fn readiness(&self, direction: Direction) -> io::Result<ReadyEvent> {
+        std::future::poll_fn(|cx| self.poll_ready(direction, cx)).await
+    }
+
+    /// Forget the readiness in `event` after an operation hit `WouldBlock`
+    ///
+    /// Closed and error states are sticky and stay set. Nothing is cleared if
+    /// another event arrived since `event` was observed.
+    pub(crate) fn clear_readiness(&self, event: ReadyEvent) {
+        let clear = event.ready & (READABLE | WRITABLE);
+        let _ = self.readiness.fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+            if current >> TICK_SHIFT != event.tick {
+                return None;
+            }
+            Some(current & !clear)
+        });
+    }
+
+    fn ready_event(&self, direction: Direction) -> Option<io::Result<ReadyEvent>> {
+        let current = self.readiness.load(Ordering::Acquire);
+        let ready = current & direction.mask();
+        if ready == 0 {
+            return None;
+        }
+        if ready & SHUTDOWN != 0 {
+            return Some(Err(super::shutdown_error()));
+        }
+
+        Some(Ok(ReadyEvent {
+            ready,
+            tick: current >> TICK_SHIFT,
+        }))
+    }
+}
+
+impl std::fmt::Debug for ScheduledIo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScheduledIo")
+            .field("token", &self.token)
+            .field("readiness", &(self.readiness.load(Ordering::Relaxed) & READINESS_MASK))
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,113 @@
+//! Slab of registered sources, addressed by generation-tagged tokens
+
+use mio::Token;
+
+/// Bits of a token holding the slot index; the generation sits above them
+const INDEX_BITS: u32 = 24;
+
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+
+/// Largest generation that fits in a token
+const MAX_GENERATION: usize = usize::MAX >> INDEX_BITS;
+
+/// Storage for registered sources that reuses the slots of removed ones
+///
+/// Every slot carries a generation that is bumped when its value is
+/// removed. Tokens encode both the slot and its generation, so an event
+/// still in flight for a removed source can't reach whatever was registered
+/// in the slot next.
+pub(crate) struct Slab<T> {
+    entries: Vec<Entry<T>>,
+
+    /// Indices of empty slots
+    free: Vec<usize>,
+}
+
+struct Entry<T> {
+    generation: usize,
+    value: Option<T>,
+}
+
+impl<T> Slab<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    /// Token the next call to `insert` will return
+    ///
+    /// Fails if every slot a token can address is taken.
+    pub(crate) fn vacant_token(&self) -> Option<Token> {
+        match self.free.last() {
+            Some(&index) => Some(token(index, self.entries[index].generation)),
+            // The last index is left out so no token collides with the reactor's waker
+            None if self.entries.len() < INDEX_MASK => Some(token(self.entries.len(), 0)),
+            None => None,
+        }
+    }
+
+    /// Store `value` in the slot named by `vacant_token`
+    pub(crate) fn insert(&mut self, value: T) -> Token {
+        match self.free.pop() {
+            Some(index) => {
+                let entry = &mut self.entries[index];
+                entry.value = Some(value);
+                token(index, entry.generation)
+            }
+            None => {
+                self.entries.push(Entry {
+                    generation: 0,
+                    value: Some(value),
+                });
+                token(self.entries.len() - 1, 0)
+            }
+        }
+    }
+
+    /// Number of values stored
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len() - self.free.len()
+    }
+
+    /// Value registered under `token`, unless it has since been removed
+    pub(crate) fn get(&self, token: Token) -> Option<&T> {
+        let entry = self.entries.get(token.0 & INDEX_MASK)?;
+        if entry.generation != token.0 >> INDEX_BITS {
+            return None;
+        }
+        entry.value.as_ref()
+    }
+
+    /// Remove the value registered under `token`, retiring the token
+    pub(crate) fn remove(&mut self, token: Token) -> Option<T> {
+        let index = token.0 & INDEX_MASK;
+        let entry = self.entries.get_mut(index)?;
+        if entry.generation != token.0 >> INDEX_BITS {
+            return None;
+        }
+
+        let value = entry.value.take()?;
+        entry.generation = if entry.generation == MAX_GENERATION { 0 } else { entry.generation + 1 };
+        self.free.push(index);
+        Some(value)
+    }
+
+    /// Remove every value
+    pub(crate) fn drain(&mut self) -> Vec<T> {
+        let mut values = Vec::new();
+        for index in 0..self.entries.len() {
+            let token = token(index, self.entries[index].generation);
+            if let Some(value) = self.remove(token) {
+                values.push(value);
+            }
+        }
+        values
+    }
+}
+
+fn token(index: usize, generation: usize) -> Token {
+    Token((generation << INDEX_BITS) | index)
+}
+
@@ -1,32 +1,464 @@
 //! High-performance async runtime with real I/O
 
-use crate::scheduler::{Scheduler, Task};
-use crate::task::{JoinHandle, TaskControl};
+use crate::blocking::{BlockingPool, PoolConfig};
+use crate::reactor::Reactor;
+use crate::scheduler::{Parker, Scheduler};
+use crate::task::{JoinHandle, Meta, Priority};
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt;
 use std::future::Future;
+use std::io;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::Wake;
 use std::thread;
 use std::time::{Duration, Instant};
 
-/// Re-export reactor for internal use
-pub use crate::reactor::REACTOR;
+pub(crate) mod driver;
+pub(crate) mod handle;
+mod owned;
+
+use driver::Driver;
+use owned::OwnedTasks;
+
+pub use handle::{EnterGuard, Handle, TryCurrentError};
 
 /// High-performance async runtime with I/O integration
 pub struct Runtime {
+    /// Shared state, also reachable through every [`Handle`]
+    handle: Handle,
+    
+    /// Worker thread handles, taken once the runtime shuts down
+    workers: Vec<thread::JoinHandle<()>>,
+    
+    /// Set by the first `close`, so dropping after an explicit shutdown
+    /// doesn't wait again without its deadline
+    closed: bool,
+}
+
+/// State shared by a runtime, its handles and its worker threads
+struct Shared {
+    /// Which scheduler flavor drives this runtime
+    flavor: RuntimeFlavor,
+    
     /// Task scheduler
     scheduler: Arc<Scheduler>,
     
-    /// Worker thread handles
-    _workers: Vec<thread::JoinHandle<()>>,
+    /// Thread hooks, used by `block_on` on current-thread runtimes
+    hooks: Hooks,
+    
+    /// Idle polls before parking, used by `block_on` on current-thread runtimes
+    spin_before_park: u32,
+    
+    /// Pool for blocking work
+    blocking: BlockingPool,
     
     /// Shutdown signal
     shutdown: Arc<AtomicBool>,
     
+    /// Tasks spawned on the runtime that haven't finished yet
+    tasks: Arc<OwnedTasks>,
+    
+    /// What to do when a spawned task panics
+    panic_handler: PanicHandler,
+    
     /// Runtime statistics
     stats: Arc<RuntimeStats>,
     
+    /// Clock that sleeps and timeouts measure time against
+    #[cfg(feature = "time")]
+    clock: crate::time::Clock,
+    
+    /// Timer driver, if time is enabled
+    #[cfg(feature = "time")]
+    timer: Option<Arc<crate::time::TimerWheel>>,
+    
+    /// I/O driver, if I/O is enabled
+    io: Option<Arc<Reactor>>,
+    
     /// Runtime start time
     start_time: Instant,
+    
+    /// Which worker threads have returned, set as they exit
+    exited: Mutex<Vec<bool>>,
+    
+    /// Notified whenever a worker thread exits
+    worker_exit: Condvar,
+}
+
+/// Scheduler flavor of a [`Runtime`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RuntimeFlavor {
+    /// All tasks run on the thread calling `block_on`
+    CurrentThread,
+    
+    /// Tasks run on a pool of worker threads
+    MultiThread,
+}
+
+/// How a runtime reacts when a spawned task panics
+///
+/// The panic is always caught and reported to the task's [`JoinHandle`] as a
+/// [`JoinError`](crate::task::JoinError) for which `is_panic()` is `true`; the
+/// worker thread keeps running either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum UnhandledPanic {
+    /// Only report the panic through the join handle
+    #[default]
+    Ignore,
+    
+    /// Also print the panic message to stderr
+    Log,
+    
+    /// Shut the runtime down: workers stop picking up tasks, the remaining
+    /// tasks are cancelled and `block_on` on a current-thread runtime panics
+    ShutdownRuntime,
+}
+
+/// Callback invoked from worker threads
+type Callback = Arc<dyn Fn() + Send + Sync>;
+
+/// Default number of idle polls a worker makes before parking
+const DEFAULT_SPIN_BEFORE_PARK: u32 = 32;
+
+/// How often (in tasks run) a worker turns the I/O and timer drivers
+/// without blocking, so events are dispatched while no worker is idle
+const DRIVER_POLL_INTERVAL: u32 = 61;
+
+/// Default cap on the number of blocking threads
+const DEFAULT_MAX_BLOCKING_THREADS: usize = 512;
+
+/// Builder for configuring a [`Runtime`]
+///
+/// ```no_run
+/// let runtime = cycle::runtime::Builder::new()
+///     .worker_threads(4)
+///     .thread_name("my-app")
+///     .enable_all()
+///     .build()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Builder {
+    /// Scheduler flavor to build
+    flavor: RuntimeFlavor,
+    
+    /// Number of worker threads, defaults to the CPU count
+    worker_threads: Option<usize>,
+    
+    /// Prefix for worker thread names
+    thread_name: String,
+    
+    /// Stack size for worker threads
+    thread_stack_size: Option<usize>,
+    
+    /// Worker thread lifecycle hooks
+    hooks: Hooks,
+    
+    /// Idle polls before a worker parks
+    spin_before_park: u32,
+    
+    /// Upper bound on threads in the blocking pool
+    max_blocking_threads: usize,
+    
+    /// How long idle blocking threads are kept around
+    keep_alive: Duration,
+    
+    /// Whether the I/O driver is started
+    enable_io: bool,
+    
+    /// Whether the timer driver is started
+    enable_time: bool,
+    
+    /// Whether the clock starts out paused
+    start_paused: bool,
+    
+    /// Reaction to panicking tasks
+    unhandled_panic: UnhandledPanic,
+}
+
+/// Worker thread lifecycle hooks
+#[derive(Clone, Default)]
+struct Hooks {
+    on_thread_start: Option<Callback>,
+    on_thread_stop: Option<Callback>,
+    on_thread_park: Option<Callback>,
+    on_thread_unpark: Option<Callback>,
+}
+
+impl Builder {
+    /// Create a builder for a multi-threaded runtime with default settings
+    pub fn new() -> Self {
+        Self::new_multi_thread()
+    }
+    
+    /// Create a builder for a multi-threaded, work-stealing runtime
+    pub fn new_multi_thread() -> Self {
+        Self::with_flavor(RuntimeFlavor::MultiThread)
+    }
+    
+    /// Create a builder for a single-threaded runtime
+    ///
+    /// No worker threads are spawned: spawned tasks only make progress while
+    /// [`Runtime::block_on`] is driving them on the calling thread, which
+    /// keeps scheduling deterministic and startup nearly free. Worker-only
+    /// settings such as [`worker_threads`](Self::worker_threads) are ignored.
+    pub fn new_current_thread() -> Self {
+        Self::with_flavor(RuntimeFlavor::CurrentThread)
+    }
+    
+    fn with_flavor(flavor: RuntimeFlavor) -> Self {
+        Self {
+            flavor,
+            worker_threads: None,
+            thread_name: "cycle-worker".to_string(),
+            thread_stack_size: None,
+            hooks: Hooks::default(),
+            spin_before_park: DEFAULT_SPIN_BEFORE_PARK,
+            max_blocking_threads: DEFAULT_MAX_BLOCKING_THREADS,
+            keep_alive: crate::blocking::DEFAULT_KEEP_ALIVE,
+            enable_io: false,
+            enable_time: false,
+            start_paused: false,
+            unhandled_panic: UnhandledPanic::default(),
+        }
+    }
+    
+    /// Set the number of worker threads
+    ///
+    /// # Panics
+    ///
+    /// Panics if `val` is zero.
+    pub fn worker_threads(&mut self, val: usize) -> &mut Self {
+        assert!(val > 0, "worker_threads must be greater than 0");
+        self.worker_threads = Some(val);
+        self
+    }
+    
+    /// Set the worker thread name prefix; workers are named `{prefix}-{id}`
+    pub fn thread_name(&mut self, prefix: impl Into<String>) -> &mut Self {
+        self.thread_name = prefix.into();
+        self
+    }
+    
+    /// Set the stack size (in bytes) for worker threads
+    pub fn thread_stack_size(&mut self, val: usize) -> &mut Self {
+        self.thread_stack_size = Some(val);
+        self
+    }
+    
+    /// Run a callback on each worker thread right after it starts
+    pub fn on_thread_start<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.hooks.on_thread_start = Some(Arc::new(f));
+        self
+    }
+    
+    /// Run a callback on each worker thread right before it exits
+    pub fn on_thread_stop<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.hooks.on_thread_stop = Some(Arc::new(f));
+        self
+    }
+    
+    /// Run a callback whenever a worker goes idle and parks
+    pub fn on_thread_park<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.hooks.on_thread_park = Some(Arc::new(f));
+        self
+    }
+    
+    /// Run a callback whenever a parked worker wakes up
+    pub fn on_thread_unpark<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.hooks.on_thread_unpark = Some(Arc::new(f));
+        self
+    }
+    
+    /// Set how many times an idle worker re-checks for work before parking
+    ///
+    /// Each check yields the thread in between. Parked workers cost no CPU but
+    /// take a few microseconds to wake; latency-sensitive deployments can
+    /// trade idle CPU for lower wake-up latency by raising this. Zero parks
+    /// as soon as the queues are empty.
+    pub fn spin_before_park(&mut self, spins: u32) -> &mut Self {
+        self.spin_before_park = spins;
+        self
+    }
+    
+    /// Set the maximum number of threads in the blocking pool
+    ///
+    /// # Panics
+    ///
+    /// Panics if `val` is zero.
+    pub fn max_blocking_threads(&mut self, val: usize) -> &mut Self {
+        assert!(val > 0, "max_blocking_threads must be greater than 0");
+        self.max_blocking_threads = val;
+        self
+    }
+    
+    /// Set how long an idle blocking thread waits for new work before exiting
+    pub fn thread_keep_alive(&mut self, duration: Duration) -> &mut Self {
+        self.keep_alive = duration;
+        self
+    }
+    
+    /// Set how the runtime reacts when a spawned task panics
+    pub fn unhandled_panic(&mut self, policy: UnhandledPanic) -> &mut Self {
+        self.unhandled_panic = policy;
+        self
+    }
+    
+    /// Enable the I/O driver
+    ///
+    /// Without it, creating sockets on this runtime fails.
+    pub fn enable_io(&mut self) -> &mut Self {
+        self.enable_io = true;
+        self
+    }
+    
+    /// Enable the timer driver
+    ///
+    /// Without it, sleeps and timeouts created on this runtime panic when polled.
+    pub fn enable_time(&mut self) -> &mut Self {
+        self.enable_time = true;
+        self
+    }
+    
+    /// Start the runtime with its clock paused, as if by [`time::pause`](crate::time::pause)
+    ///
+    /// Only current-thread runtimes support a paused clock; building any
+    /// other flavor with this set fails.
+    #[cfg(feature = "time")]
+    pub fn start_paused(&mut self, start_paused: bool) -> &mut Self {
+        self.start_paused = start_paused;
+        self
+    }
+    
+    /// Enable both the I/O and timer drivers
+    pub fn enable_all(&mut self) -> &mut Self {
+        self.enable_io().enable_time()
+    }
+    
+    /// Build the runtime, spawning its worker threads
+    pub fn build(&mut self) -> io::Result<Runtime> {
+        if self.start_paused && self.flavor != RuntimeFlavor::CurrentThread {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "`start_paused` requires a current-thread runtime",
+            ));
+        }
+        
+        let num_workers = match self.flavor {
+            RuntimeFlavor::CurrentThread => 1,
+            RuntimeFlavor::MultiThread => self.worker_threads.unwrap_or_else(num_cpus::get),
+        };
+        // Drivers have no threads of their own: idle workers turn them, so
+        // building a current-thread runtime never spawns a thread
+        let io = if self.enable_io {
+            Some(Arc::new(Reactor::new()?))
+        } else {
+            None
+        };
+        #[cfg(feature = "time")]
+        let timer = self.enable_time.then(|| Arc::new(crate::time::TimerWheel::new()));
+        #[cfg(feature = "time")]
+        let has_timer = timer.is_some();
+        #[cfg(not(feature = "time"))]
+        let has_timer = false;
+        let driver = (io.is_some() || has_timer).then(|| {
+            Arc::new(Driver::new(
+                io.clone(),
+                #[cfg(feature = "time")]
+                timer.clone(),
+            ))
+        });
+        
+        let scheduler = Arc::new(Scheduler::with_driver(num_workers, driver));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stats = Arc::new(RuntimeStats::default());
+        let blocking = BlockingPool::new(PoolConfig {
+            thread_name: self.thread_name.clone(),
+            stack_size: self.thread_stack_size,
+            max_threads: self.max_blocking_threads,
+            keep_alive: self.keep_alive,
+        });
+        
+        let tasks = Arc::new(OwnedTasks::new());
+        let panic_handler = PanicHandler {
+            policy: self.unhandled_panic,
+            scheduler: scheduler.clone(),
+            shutdown: shutdown.clone(),
+            tasks: tasks.clone(),
+        };
+        
+        let handle = Handle {
+            shared: Arc::new(Shared {
+                flavor: self.flavor,
+                scheduler,
+                hooks: self.hooks.clone(),
+                spin_before_park: self.spin_before_park,
+                blocking,
+                shutdown,
+                tasks,
+                panic_handler,
+                stats,
+                #[cfg(feature = "time")]
+                clock: crate::time::Clock::new(self.start_paused),
+                #[cfg(feature = "time")]
+                timer,
+                io,
+                start_time: Instant::now(),
+                exited: Mutex::new(vec![false; num_workers]),
+                worker_exit: Condvar::new(),
+            }),
+        };
+        
+        let workers = match self.flavor {
+            RuntimeFlavor::CurrentThread => Vec::new(),
+            RuntimeFlavor::MultiThread => Runtime::start_workers(self, num_workers, &handle)?,
+        };
+        
+        Ok(Runtime {
+            handle,
+            workers,
+            closed: false,
+        })
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Builder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Builder")
+            .field("flavor", &self.flavor)
+            .field("worker_threads", &self.worker_threads)
+            .field("thread_name", &self.thread_name)
+            .field("thread_stack_size", &self.thread_stack_size)
+            .field("spin_before_park", &self.spin_before_park)
+            .field("max_blocking_threads", &self.max_blocking_threads)
+            .field("thread_keep_alive", &self.keep_alive)
+            .field("enable_io", &self.enable_io)
+            .field("enable_time", &self.enable_time)
+            .field("start_paused", &self.start_paused)
+            .field("unhandled_panic", &self.unhandled_panic)
+            .finish()
+    }
 }
 
 /// Runtime performance statistics
@@ -48,155 +480,484 @@ pub struct RuntimeStats {
     pub timer_operations: AtomicU64,
 }
 
+/// Applies the runtime's [`UnhandledPanic`] policy to a panicking task
+#[derive(Clone)]
+struct PanicHandler {
+    policy: UnhandledPanic,
+    scheduler: Arc<Scheduler>,
+    shutdown: Arc<AtomicBool>,
+    tasks: Arc<OwnedTasks>,
+}
+
+impl PanicHandler {
+    fn handle(&self, payload: &(dyn Any + Send)) {
+        match self.policy {
+            UnhandledPanic::Ignore => {}
+            UnhandledPanic::Log => match crate::task::panic_message(payload) {
+                Some(message) => eprintln!("cycle: spawned task panicked: {}", message),
+                None => eprintln!("cycle: spawned task panicked"),
+            },
+            UnhandledPanic::ShutdownRuntime => {
+                // Cancel the remaining tasks, as `Runtime::close` would, so
+                // their handles resolve instead of waiting on stopped workers
+                self.tasks.close();
+                self.shutdown.store(true, Ordering::Release);
+                self.scheduler.unpark_all();
+                self.tasks.shutdown();
+            }
+        }
+    }
+}
+
+/// Counts a spawned task as completed when its future is dropped, whether it
+/// ran to completion or was aborted
+struct FinishedTask {
+    stats: Arc<RuntimeStats>,
+    tasks: Arc<OwnedTasks>,
+    id: crate::task::Id,
+}
+
+impl Drop for FinishedTask {
+    fn drop(&mut self) {
+        self.stats.tasks_completed.fetch_add(1, Ordering::Relaxed);
+        self.stats.active_tasks.fetch_sub(1, Ordering::Relaxed);
+        self.tasks.remove(self.id);
+    }
+}
+
 impl Runtime {
-    /// Create new runtime with optimal worker count
-    pub fn new() -> Self {
-        let num_workers = num_cpus::get();
-        Self::with_workers(num_workers)
+    /// Create new runtime with optimal worker count and every driver enabled
+    pub fn new() -> io::Result<Self> {
+        Builder::new().enable_all().build()
     }
     
-    /// Create runtime with specific worker count
-    pub fn with_workers(num_workers: usize) -> Self {
-        let scheduler = Arc::new(Scheduler::new(num_workers));
-        let shutdown = Arc::new(AtomicBool::new(false));
-        let stats = Arc::new(RuntimeStats::default());
-        
-        // Initialize reactor
-        once_cell::sync::Lazy::force(&REACTOR);
-        
-        // Start worker threads
-        let workers = Self::start_workers(num_workers, scheduler.clone(), shutdown.clone());
-        
-        Self {
-            scheduler,
-            _workers: workers,
-            shutdown,
-            stats,
-            start_time: Instant::now(),
-        }
+    /// Create runtime with specific worker count and every driver enabled
+    pub fn with_workers(num_workers: usize) -> io::Result<Self> {
+        Builder::new().worker_threads(num_workers).enable_all().build()
+    }
+    
+    /// Create a [`Builder`] for configuring a runtime
+    pub fn builder() -> Builder {
+        Builder::new()
     }
     
     /// Start worker threads
     fn start_workers(
+        builder: &Builder,
         num_workers: usize,
-        scheduler: Arc<Scheduler>,
-        shutdown: Arc<AtomicBool>,
-    ) -> Vec<thread::JoinHandle<()>> {
+        handle: &Handle,
+    ) -> io::Result<Vec<thread::JoinHandle<()>>> {
         let mut workers = Vec::with_capacity(num_workers);
         
         for worker_id in 0..num_workers {
-            let scheduler = scheduler.clone();
-            let shutdown = shutdown.clone();
+            let hooks = builder.hooks.clone();
+            let worker = WorkerContext {
+                id: worker_id,
+                handle: handle.clone(),
+            };
             
-            let handle = thread::Builder::new()
-                .name(format!("cycle-worker-{}", worker_id))
-                .spawn(move || {
-                    Self::worker_main(worker_id, scheduler, shutdown);
-                })
-                .expect("Failed to spawn worker thread");
+            let mut thread_builder = thread::Builder::new()
+                .name(format!("{}-{}", builder.thread_name, worker_id));
+            
+            if let Some(stack_size) = builder.thread_stack_size {
+                thread_builder = thread_builder.stack_size(stack_size);
+            }
             
-            workers.push(handle);
+            let spawned = thread_builder.spawn(move || {
+                // Reports the exit to `close` even if a hook panics
+                let _exit = WorkerExit(&worker);
+                
+                if let Some(f) = &hooks.on_thread_start {
+                    f();
+                }
+                
+                worker.run(|| false);
+                
+                if let Some(f) = &hooks.on_thread_stop {
+                    f();
+                }
+            });
+            
+            match spawned {
+                Ok(handle) => workers.push(handle),
+                Err(e) => {
+                    // Tear down the workers that did start before reporting
+                    handle.shared.shutdown.store(true, Ordering::Release);
+                    handle.shared.scheduler.unpark_all();
+                    for worker in workers {
+                        let _ = worker.join();
+                    }
+                    return Err(e);
+                }
+            }
         }
         
-        workers
+        Ok(workers)
     }
     
-    /// Worker thread main loop
-    fn worker_main(
+    /// Run tasks from the given queue until `done` returns `true`
+    ///
+    /// This is the main loop of every worker thread, and of `block_on` on a
+    /// current-thread runtime.
+    ///
+    /// Whoever makes `done` true must unpark the worker's parker.
+    fn run_tasks(
         worker_id: usize,
-        scheduler: Arc<Scheduler>,
-        shutdown: Arc<AtomicBool>,
+        scheduler: &Scheduler,
+        hooks: &Hooks,
+        spin_before_park: u32,
+        done: impl Fn() -> bool,
     ) {
-        let mut idle_count = 0;
-        const MAX_IDLE: usize = 1000;
+        let parker = scheduler.parker(worker_id);
         
-        while !shutdown.load(Ordering::Acquire) {
+        // The queue may be held by a thread leaving `block_in_place`, or by
+        // another `block_on` on a current-thread runtime; whoever holds it
+        // unparks us when letting go
+        let _local = loop {
+            let epoch = parker.epoch();
+            if done() {
+                return;
+            }
+            
+            if let Some(local) = scheduler.try_claim(worker_id) {
+                break local;
+            }
+            parker.park(epoch);
+        };
+        
+        let mut spins = 0;
+        let mut ticks: u32 = 0;
+        
+        loop {
+            // Read before checking `done` so a concurrent unpark is never missed
+            let epoch = parker.epoch();
+            
+            if done() {
+                break;
+            }
+            
             if let Some(task) = scheduler.find_work(worker_id) {
-                // Execute task
-                task();
-                idle_count = 0;
+                // Poll task until it completes or yields
+                task.run();
+                spins = 0;
+                
+                ticks = ticks.wrapping_add(1);
+                if ticks.is_multiple_of(DRIVER_POLL_INTERVAL) {
+                    scheduler.poll_driver();
+                }
+            } else if spins < spin_before_park {
+                spins += 1;
+                thread::yield_now();
             } else {
-                // No work available
-                idle_count += 1;
+                if let Some(f) = &hooks.on_thread_park {
+                    f();
+                }
                 
-                if idle_count < MAX_IDLE {
-                    thread::yield_now();
-                } else {
-                    thread::sleep(Duration::from_micros(100));
-                    idle_count = 0;
+                scheduler.park(worker_id, epoch);
+                spins = 0;
+                
+                if let Some(f) = &hooks.on_thread_unpark {
+                    f();
                 }
             }
         }
     }
     
     /// Spawn a task on this runtime
+    #[track_caller]
     pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
     where
         F: Future + Send + 'static,
         F::Output: Send + 'static,
     {
-        self.stats.tasks_spawned.fetch_add(1, Ordering::Relaxed);
-        self.stats.active_tasks.fetch_add(1, Ordering::Relaxed);
-        
-        let control = TaskControl::new();
-        let handle = JoinHandle::new(control.clone());
-        
-        let control_clone = control.clone();
-        let stats_clone = self.stats.clone();
-        
-        // Create task that executes the future
-        let task: Task = Box::new(move || {
-            // Execute future using futures executor
-            let result = futures_executor::block_on(future);
-            control_clone.complete(Ok(result));
-            
-            stats_clone.tasks_completed.fetch_add(1, Ordering::Relaxed);
-            stats_clone.active_tasks.fetch_sub(1, Ordering::Relaxed);
-        });
-        
-        self.scheduler.schedule(task);
-        handle
+        self.handle.spawn(future)
     }
     
-    /// Block on a future
-    pub fn block_on<F>(&self, future: F) -> F::Output
+    /// Spawn a task on this runtime with the given scheduling priority
+    #[track_caller]
+    pub fn spawn_with_priority<F>(&self, priority: Priority, future: F) -> JoinHandle<F::Output>
     where
         F: Future + Send + 'static,
         F::Output: Send + 'static,
     {
-        let handle = self.spawn(future);
-        
-        // Busy wait for completion with yielding
-        while !handle.is_finished() {
-            thread::yield_now();
-        }
-        
-        handle.try_result().unwrap().expect("Task failed")
+        self.handle.spawn_with_priority(priority, future)
+    }
+    
+    /// Run a blocking closure on this runtime's blocking pool
+    #[track_caller]
+    pub fn spawn_blocking<F, R>(&self, f: F) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.handle.spawn_blocking(f)
+    }
+    
+    /// Run a future to completion on the calling thread
+    ///
+    /// See [`Handle::block_on`]; the future may borrow from the caller and
+    /// need not be `Send`.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.handle.block_on(future)
+    }
+    
+    /// Get a cloneable handle to this runtime
+    pub fn handle(&self) -> &Handle {
+        &self.handle
+    }
+    
+    /// Make this runtime current on the calling thread until the guard is dropped
+    ///
+    /// While entered, [`spawn`](crate::spawn) and [`Handle::current`] resolve to this runtime.
+    pub fn enter(&self) -> EnterGuard<'_> {
+        self.handle.enter()
+    }
+    
+    /// Scheduler flavor of this runtime
+    pub fn flavor(&self) -> RuntimeFlavor {
+        self.handle.flavor()
     }
     
     /// Get runtime statistics
     pub fn stats(&self) -> RuntimeStatsSnapshot {
-        RuntimeStatsSnapshot {
-            uptime: self.start_time.elapsed(),
-            tasks_spawned: self.stats.tasks_spawned.load(Ordering::Relaxed),
-            tasks_completed: self.stats.tasks_completed.load(Ordering::Relaxed),
-            active_tasks: self.stats.active_tasks.load(Ordering::Relaxed),
-            io_operations: self.stats.io_operations.load(Ordering::Relaxed),
-            timer_operations: self.stats.timer_operations.load(Ordering::Relaxed),
-        }
+        self.handle.stats()
     }
     
-    /// Shutdown the runtime gracefully
+    /// Shut the runtime down, cancelling every task still running
+    ///
+    /// Same as dropping the runtime: see [`Runtime::shutdown_timeout`].
     pub fn shutdown(self) {
-        self.shutdown.store(true, Ordering::Release);
+        drop(self);
+    }
+    
+    /// Shut the runtime down, giving in-flight tasks up to `timeout` to finish
+    ///
+    /// New spawns are rejected right away and their handles resolve to a
+    /// cancelled [`JoinError`](crate::task::JoinError). Tasks still pending
+    /// once the timeout expires are cancelled: their futures are dropped and
+    /// their handles resolve as cancelled too. Worker and blocking threads
+    /// that haven't exited by then are detached rather than waited for.
+    ///
+    /// Tasks of a current-thread runtime only run inside `block_on`, so they
+    /// are cancelled straight away.
+    ///
+    /// Dropping the runtime does the same without draining: remaining tasks
+    /// are cancelled immediately, but the drop blocks until every worker and
+    /// blocking thread has exited. Either way the runtime's pending timers
+    /// are dropped, and sockets created on it stop working.
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    ///
+    /// let runtime = cycle::runtime::Runtime::new()?;
+    /// runtime.spawn(async {
+    ///     cycle::time::sleep(Duration::from_millis(50)).await;
+    ///     println!("finished before shutdown");
+    /// });
+    ///
+    /// runtime.shutdown_timeout(Duration::from_secs(1));
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn shutdown_timeout(mut self, timeout: Duration) {
+        self.close(Some(Instant::now() + timeout));
+    }
+    
+    /// Shut the runtime down without waiting for anything
+    ///
+    /// Remaining tasks are cancelled and worker threads are left to exit on
+    /// their own. Useful for dropping a runtime from within an async context,
+    /// where blocking is not allowed.
+    pub fn shutdown_background(self) {
+        self.shutdown_timeout(Duration::ZERO);
+    }
+    
+    /// Stop the runtime, waiting for its threads until `deadline`, or for as long as it takes
+    fn close(&mut self, deadline: Option<Instant>) {
+        if std::mem::replace(&mut self.closed, true) {
+            return;
+        }
+        
+        let shared = &self.handle.shared;
+        shared.tasks.close();
+        
+        if let (Some(deadline), RuntimeFlavor::MultiThread) = (deadline, shared.flavor) {
+            shared.tasks.wait_empty(deadline);
+        }
+        
+        shared.shutdown.store(true, Ordering::Release);
+        shared.scheduler.unpark_all();
         
-        // Wait for workers to finish
-        for worker in self._workers {
+        // A runtime dropped from one of its own workers can't wait for that one
+        let current = thread::current().id();
+        for (id, worker) in std::mem::take(&mut self.workers).into_iter().enumerate() {
+            if worker.thread().id() == current {
+                continue;
+            }
+            // Workers still running at the deadline are left detached
+            if let Some(deadline) = deadline {
+                if !shared.wait_worker_exit(id, deadline) {
+                    continue;
+                }
+            }
             let _ = worker.join();
         }
         
-        // Shutdown reactor
-        REACTOR.shutdown();
+        shared.tasks.shutdown();
+        shared.scheduler.clear();
+        shared.blocking.shutdown(deadline);
+        
+        #[cfg(feature = "time")]
+        if let Some(timer) = &shared.timer {
+            timer.shutdown();
+        }
+        if let Some(io) = &shared.io {
+            io.shutdown();
+        }
+    }
+}
+
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        self.close(None);
+    }
+}
+
+thread_local! {
+    /// Worker of the multi-threaded runtime driving this thread, if any
+    static WORKER: RefCell<Option<WorkerContext>> = const { RefCell::new(None) };
+}
+
+/// Everything a thread needs to act as one of a runtime's workers
+impl Shared {
+    /// Wait until worker `id` has exited, giving up at `deadline`
+    fn wait_worker_exit(&self, id: usize, deadline: Instant) -> bool {
+        let mut exited = self.exited.lock().unwrap();
+        while !exited[id] {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            exited = self.worker_exit.wait_timeout(exited, deadline - now).unwrap().0;
+        }
+        true
+    }
+}
+
+/// Marks a worker thread as exited when dropped
+struct WorkerExit<'a>(&'a WorkerContext);
+
+impl Drop for WorkerExit<'_> {
+    fn drop(&mut self) {
+        let shared = &self.0.handle.shared;
+        shared.exited.lock().unwrap_or_else(|e| e.into_inner())[self.0.id] = true;
+        shared.worker_exit.notify_all();
+    }
+}
+
+#[derive(Clone)]
+struct WorkerContext {
+    id: usize,
+    handle: Handle,
+}
+
+impl WorkerContext {
+    /// Serve this worker's queue on the current thread until shutdown or `done`
+    fn run(&self, done: impl Fn() -> bool) {
+        let prev = WORKER.with(|w| w.borrow_mut().replace(self.clone()));
+        let _enter = self.handle.enter();
+        let shared = &self.handle.shared;
+        
+        Runtime::run_tasks(self.id, &shared.scheduler, &shared.hooks, shared.spin_before_park, || {
+            done() || shared.shutdown.load(Ordering::Acquire)
+        });
+        
+        WORKER.with(|w| *w.borrow_mut() = prev);
+    }
+}
+
+/// Run a blocking closure on the blocking pool of the current runtime,
+/// or of the global runtime outside of one
+#[track_caller]
+pub(crate) fn spawn_blocking<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let meta = Meta::new(None, std::panic::Location::caller());
+    handle::with_current(|handle| handle.spawn_blocking_task(meta, f))
+}
+
+/// Run `f` on the current thread, handing this worker's queue to a
+/// replacement thread until `f` returns
+pub(crate) fn block_in_place<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    struct Reset {
+        done: Arc<AtomicBool>,
+        worker: Option<WorkerContext>,
+        released: Option<usize>,
+    }
+    
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            self.done.store(true, Ordering::Release);
+            let worker = self.worker.take();
+            
+            // Kick the replacement out of `park` so it notices
+            if let Some(worker) = &worker {
+                let scheduler = &worker.handle.shared.scheduler;
+                scheduler.unpark(worker.id);
+                
+                if let Some(worker_id) = self.released {
+                    scheduler.reclaim_local(worker_id);
+                }
+            }
+            WORKER.with(|w| *w.borrow_mut() = worker);
+        }
+    }
+    
+    // Not on a worker thread: nothing to hand off
+    let worker = match WORKER.with(|w| w.borrow_mut().take()) {
+        Some(worker) => worker,
+        None => return f(),
+    };
+    
+    // Give up the local queue so the replacement can claim it
+    let released = worker.handle.shared.scheduler.release_local();
+    
+    let done = Arc::new(AtomicBool::new(false));
+    let replacement = worker.clone();
+    let replacement_done = done.clone();
+    
+    // If the pool refuses the job, other workers still steal from our queue
+    let _ = worker.handle.shared.blocking.submit(Box::new(move || {
+        replacement.run(|| replacement_done.load(Ordering::Acquire));
+    }));
+    
+    let _reset = Reset {
+        done,
+        worker: Some(worker),
+        released,
+    };
+    
+    f()
+}
+
+/// Waker that unparks the thread blocked in `block_on`
+struct ThreadWaker {
+    parker: Arc<Parker>,
+    notified: AtomicBool,
+}
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+    
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.notified.store(true, Ordering::Release);
+        self.parker.unpark();
     }
 }
 
@@ -215,6 +976,8 @@ pub struct RuntimeStatsSnapshot {
     pub io_operations: u64,
     /// Total number of timer operations completed
     pub timer_operations: u64,
+    /// Current number of I/O sources registered with the reactor
+    pub io_sources: u64,
 }
 
 impl RuntimeStatsSnapshot {
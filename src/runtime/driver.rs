@@ -0,0 +1,113 @@
+//! I/O and timer drivers of a runtime, turned by its idle workers
+
+use crate::reactor::Reactor;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// The reactor and timer wheel of a runtime
+///
+/// There is no driver thread. A worker with nothing to do takes the driver
+/// and blocks on it until an I/O event arrives, the next timer is due or it
+/// is unparked; the other idle workers park as usual. Busy workers turn it
+/// without blocking every so often, so events aren't held up while nobody
+/// is idle.
+pub(crate) struct Driver {
+    io: Option<Arc<Reactor>>,
+
+    #[cfg(feature = "time")]
+    timer: Option<Arc<crate::time::TimerWheel>>,
+
+    /// Held by the thread turning the driver
+    owner: Mutex<()>,
+
+    /// Set by `unpark` when there is no reactor to interrupt
+    notified: Mutex<bool>,
+    condvar: Condvar,
+}
+
+/// Exclusive right to turn a driver, released when dropped
+pub(crate) struct Turn<'a> {
+    driver: &'a Driver,
+    _owner: MutexGuard<'a, ()>,
+}
+
+impl Driver {
+    pub(crate) fn new(
+        io: Option<Arc<Reactor>>,
+        #[cfg(feature = "time")] timer: Option<Arc<crate::time::TimerWheel>>,
+    ) -> Self {
+        Self {
+            io,
+            #[cfg(feature = "time")]
+            timer,
+            owner: Mutex::new(()),
+            notified: Mutex::new(false),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// Take the driver, unless another thread is turning it
+    pub(crate) fn try_turn(&self) -> Option<Turn<'_>> {
+        self.owner.try_lock().ok().map(|owner| Turn {
+            driver: self,
+            _owner: owner,
+        })
+    }
+
+    /// Wake the thread blocked on the driver, or make the next block return at once
+    pub(crate) fn unpark(&self) {
+        match &self.io {
+            Some(io) => io.wake(),
+            None => {
+                *self.notified.lock().unwrap() = true;
+                self.condvar.notify_one();
+            }
+        }
+    }
+
+    /// How long a blocking turn may wait before the next timer is due
+    fn next_timeout(&self) -> Option<Duration> {
+        #[cfg(feature = "time")]
+        if let Some(timer) = &self.timer {
+            return timer.next_deadline().map(|deadline| deadline.saturating_duration_since(std::time::Instant::now()));
+        }
+        None
+    }
+
+    /// Block until `unpark` or `timeout`, for drivers without a reactor
+    fn wait(&self, timeout: Option<Duration>) {
+        let mut notified = self.notified.lock().unwrap();
+        if !*notified {
+            notified = match timeout {
+                Some(timeout) => self.condvar.wait_timeout(notified, timeout).unwrap().0,
+                None => self.condvar.wait(notified).unwrap(),
+            };
+        }
+        *notified = false;
+    }
+}
+
+impl Turn<'_> {
+    /// Block until an I/O event, the next timer or an unpark, then dispatch
+    /// whatever is ready
+    pub(crate) fn park(&self) {
+        self.turn(self.driver.next_timeout());
+    }
+
+    /// Dispatch ready I/O and expired timers without blocking
+    pub(crate) fn poll(&self) {
+        self.turn(Some(Duration::ZERO));
+    }
+
+    fn turn(&self, timeout: Option<Duration>) {
+        match &self.driver.io {
+            Some(io) => io.turn(timeout),
+            None => self.driver.wait(timeout),
+        }
+
+        #[cfg(feature = "time")]
+        if let Some(timer) = &self.driver.timer {
+            timer.fire_expired();
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! Cloneable runtime handles and the thread-local current runtime
+
+use super::{FinishedTask, Runtime, RuntimeFlavor, RuntimeStatsSnapshot, Shared, ThreadWaker};
+use crate::reactor::Reactor;
+use crate::scheduler::{Parker, Task};
+use crate::task::{CompletionGuard, JoinError, JoinHandle, Meta, Priority, TaskControl, Tracked};
+use futures::FutureExt;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::marker::PhantomData;
+use std::panic::{AssertUnwindSafe, Location};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+/// Queued tasks a current-thread `block_on` runs between polls of its future
+const MAX_TASKS_PER_TICK: u32 = 61;
+
+thread_local! {
+    /// Runtime entered on this thread, if any
+    static CURRENT: RefCell<Option<Handle>> = const { RefCell::new(None) };
+}
+
+/// Cloneable, `Send` reference to a [`Runtime`]
+///
+/// Worker threads, `block_on` and [`Runtime::enter`] make a runtime current
+/// on their thread; [`Handle::current`] returns it, and free functions such
+/// as [`spawn`](crate::spawn) target it instead of the global runtime.
+///
+/// ```no_run
+/// use cycle::runtime::{Handle, Runtime};
+///
+/// let runtime = Runtime::new()?;
+/// let handle = runtime.handle().clone();
+///
+/// std::thread::spawn(move || {
+///     handle.spawn(async { println!("spawned from another thread") });
+/// });
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct Handle {
+    pub(super) shared: Arc<Shared>,
+}
+
+/// Restores the previously current runtime when dropped
+///
+/// Returned by [`Runtime::enter`] and [`Handle::enter`].
+pub struct EnterGuard<'a> {
+    prev: Option<Handle>,
+
+    /// Guards must be dropped on the thread that created them
+    _marker: PhantomData<(&'a Handle, *const ())>,
+}
+
+/// Error returned by [`Handle::try_current`] outside of a runtime
+#[derive(Debug, Clone)]
+pub struct TryCurrentError(());
+
+impl Handle {
+    /// Handle to the runtime current on this thread
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a runtime; see [`Handle::try_current`].
+    pub fn current() -> Self {
+        match Self::try_current() {
+            Ok(handle) => handle,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Handle to the runtime current on this thread, if any
+    pub fn try_current() -> Result<Self, TryCurrentError> {
+        CURRENT.with(|current| current.borrow().clone().ok_or(TryCurrentError(())))
+    }
+
+    /// Make this runtime current on the calling thread until the guard is dropped
+    pub fn enter(&self) -> EnterGuard<'_> {
+        let prev = CURRENT.with(|current| current.borrow_mut().replace(self.clone()));
+        EnterGuard {
+            prev,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Spawn a task on this runtime
+    #[track_caller]
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.spawn_with_priority(Priority::Normal, future)
+    }
+
+    /// Spawn a task on this runtime with the given scheduling priority
+    #[track_caller]
+    pub fn spawn_with_priority<F>(&self, priority: Priority, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.spawn_task(Meta::new(None, Location::caller()), priority, future)
+    }
+
+    /// Spawn the task described by `meta`
+    pub(crate) fn spawn_task<F>(&self, meta: Arc<Meta>, priority: Priority, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let shared = &self.shared;
+        shared.stats.tasks_spawned.fetch_add(1, Ordering::Relaxed);
+        shared.stats.active_tasks.fetch_add(1, Ordering::Relaxed);
+
+        let control = TaskControl::new();
+        let completion = CompletionGuard::new(control.clone());
+        let finished = FinishedTask {
+            stats: shared.stats.clone(),
+            tasks: shared.tasks.clone(),
+            id: meta.id,
+        };
+        let panic_handler = shared.panic_handler.clone();
+
+        // Wrap the future so its output or panic lands in the join handle; if
+        // the task is aborted the guards complete it as cancelled on drop
+        let task = Task::new(Tracked::new(meta.id, async move {
+            let _finished = finished;
+            let result = AssertUnwindSafe(future).catch_unwind().await;
+            if let Err(payload) = &result {
+                panic_handler.handle(&**payload);
+            }
+            completion.complete(result.map_err(JoinError::panic));
+        }), shared.scheduler.clone(), priority);
+
+        let handle = JoinHandle::with_abort(control, task.remote_abort(), meta.clone());
+
+        // A runtime that is shutting down cancels the task instead of running it
+        let header = task.header();
+        if !shared.tasks.insert(meta.id, header.clone()) {
+            header.shutdown();
+            return handle;
+        }
+
+        shared.scheduler.schedule(task);
+        handle
+    }
+
+    /// Run a blocking closure on this runtime's blocking pool
+    #[track_caller]
+    pub fn spawn_blocking<F, R>(&self, f: F) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.spawn_blocking_task(Meta::new(None, Location::caller()), f)
+    }
+
+    /// Run the blocking task described by `meta`
+    pub(crate) fn spawn_blocking_task<F, R>(&self, meta: Arc<Meta>, f: F) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.shared.blocking.spawn(meta, f)
+    }
+
+    /// Run a future to completion on the calling thread
+    ///
+    /// The future is polled right here, parking the thread between wakeups, so
+    /// it needn't be `Send` or `'static` and may borrow from the caller. The
+    /// runtime is current while it runs, so anything it spawns goes to the
+    /// runtime's workers; on a current-thread runtime the run queue is driven
+    /// while waiting.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let shared = &self.shared;
+        let _enter = self.enter();
+
+        // Current-thread runtimes park on the scheduler so new tasks wake us too
+        let parker = match shared.flavor {
+            RuntimeFlavor::CurrentThread => shared.scheduler.parker(0).clone(),
+            RuntimeFlavor::MultiThread => Arc::new(Parker::new()),
+        };
+        let thread_waker = Arc::new(ThreadWaker {
+            parker: parker.clone(),
+            notified: AtomicBool::new(false),
+        });
+        let waker = Waker::from(thread_waker.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+
+        loop {
+            if let Poll::Ready(output) = crate::coop::budget(|| future.as_mut().poll(&mut cx)) {
+                return output;
+            }
+
+            match shared.flavor {
+                RuntimeFlavor::CurrentThread => {
+                    // Run some of the queued tasks even if the future woke itself
+                    // right away, so a busy root future can't starve them
+                    let tick = Cell::new(0);
+                    Runtime::run_tasks(0, &shared.scheduler, &shared.hooks, shared.spin_before_park, || {
+                        tick.set(tick.get() + 1);
+                        let notified = thread_waker.notified.load(Ordering::Acquire)
+                            && (tick.get() > MAX_TASKS_PER_TICK || !shared.scheduler.has_work());
+                        notified || shared.shutdown.load(Ordering::Acquire) || self.auto_advance()
+                    });
+
+                    if shared.shutdown.load(Ordering::Acquire) {
+                        panic!("runtime was shut down while `block_on` was running");
+                    }
+                }
+                RuntimeFlavor::MultiThread => loop {
+                    let epoch = parker.epoch();
+                    if thread_waker.notified.load(Ordering::Acquire) {
+                        break;
+                    }
+                    parker.park(epoch);
+                },
+            }
+
+            thread_waker.notified.store(false, Ordering::Release);
+        }
+    }
+
+    /// Scheduler flavor of the runtime
+    pub fn flavor(&self) -> RuntimeFlavor {
+        self.shared.flavor
+    }
+
+    /// Clock of this runtime
+    #[cfg(feature = "time")]
+    pub(crate) fn clock(&self) -> &crate::time::Clock {
+        &self.shared.clock
+    }
+
+    /// Timer driver of this runtime
+    ///
+    /// # Panics
+    ///
+    /// Panics if the runtime was built without time enabled.
+    #[cfg(feature = "time")]
+    pub(crate) fn timer(&self) -> &Arc<crate::time::TimerWheel> {
+        self.shared
+            .timer
+            .as_ref()
+            .expect("timers are disabled on this runtime; enable them with `Builder::enable_time`")
+    }
+
+    /// Wake the worker blocked on this runtime's drivers so it re-arms its timeout
+    #[cfg(feature = "time")]
+    pub(crate) fn unpark_driver(&self) {
+        self.shared.scheduler.unpark_driver();
+    }
+
+    /// I/O driver of this runtime, or an error if the runtime was built without I/O enabled
+    pub(crate) fn reactor(&self) -> io::Result<&Arc<Reactor>> {
+        self.shared.io.as_ref().ok_or_else(|| {
+            io::Error::other("I/O is disabled on this runtime; enable it with `Builder::enable_io`")
+        })
+    }
+
+    /// Jump a paused clock to the next timer once no tasks are runnable,
+    /// returning `true` if that fired a timer
+    fn auto_advance(&self) -> bool {
+        #[cfg(feature = "time")]
+        if self.shared.clock.is_paused() && !self.shared.scheduler.has_work() {
+            return self.shared.clock.auto_advance();
+        }
+        false
+    }
+
+    /// Get runtime statistics
+    pub fn stats(&self) -> RuntimeStatsSnapshot {
+        let shared = &self.shared;
+        RuntimeStatsSnapshot {
+            uptime: shared.start_time.elapsed(),
+            tasks_spawned: shared.stats.tasks_spawned.load(Ordering::Relaxed),
+            tasks_completed: shared.stats.tasks_completed.load(Ordering::Relaxed),
+            active_tasks: shared.stats.active_tasks.load(Ordering::Relaxed),
+            io_operations: shared.stats.io_operations.load(Ordering::Relaxed),
+            timer_operations: shared.stats.timer_operations.load(Ordering::Relaxed),
+            io_sources: shared.io.as_ref().map_or(0, |io| io.num_sources() as u64),
+        }
+    }
+}
+
+/// Run `f` with the current runtime, or the global runtime outside of one
+pub(crate) fn with_current<R>(f: impl FnOnce(&Handle) -> R) -> R {
+    let current = CURRENT.with(|current| current.borrow().clone());
+    match current {
+        Some(handle) => f(&handle),
+        None => f(crate::GLOBAL_RUNTIME.handle()),
+    }
+}
+
+impl fmt::Debug for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handle").field("flavor", &self.shared.flavor).finish_non_exhaustive()
+    }
+}
+
+impl Drop for EnterGuard<'_> {
+    fn drop(&mut self) {
+        let prev = self.prev.take();
+        CURRENT.with(|current| *current.borrow_mut() = prev);
+    }
+}
+
+impl fmt::Debug for EnterGuard<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnterGuard").finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for TryCurrentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no cycle runtime is running on this thread")
+    }
+}
+
+impl std::error::Error for TryCurrentError {}
@@ -0,0 +1,78 @@
+//! Registry of a runtime's live tasks, used to drain and cancel them on shutdown
+
+use crate::task::{Header, Id};
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Instant;
+
+/// Every task spawned on a runtime that hasn't been dropped yet
+pub(crate) struct OwnedTasks {
+    state: Mutex<State>,
+
+    /// Notified when the last task is removed
+    condvar: Condvar,
+}
+
+struct State {
+    /// Set once the runtime stops accepting spawns
+    closed: bool,
+
+    tasks: HashMap<Id, Arc<Header>>,
+}
+
+impl OwnedTasks {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                closed: false,
+                tasks: HashMap::new(),
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// Track a newly spawned task, returning `false` once the runtime is closed
+    pub(crate) fn insert(&self, id: Id, task: Arc<Header>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return false;
+        }
+        state.tasks.insert(id, task);
+        true
+    }
+
+    /// Forget a task whose future has been dropped
+    pub(crate) fn remove(&self, id: Id) {
+        let mut state = self.state.lock().unwrap();
+        if state.tasks.remove(&id).is_some() && state.tasks.is_empty() {
+            self.condvar.notify_all();
+        }
+    }
+
+    /// Reject every later spawn
+    pub(crate) fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+    }
+
+    /// Wait until every task has finished, returning `false` if `deadline` passed first
+    pub(crate) fn wait_empty(&self, deadline: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while !state.tasks.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            state = self.condvar.wait_timeout(state, deadline - now).unwrap().0;
+        }
+        true
+    }
+
+    /// Cancel every remaining task, completing their handles as cancelled
+    pub(crate) fn shutdown(&self) {
+        // Dropping a future removes it from the map, so don't hold the lock meanwhile
+        let tasks = std::mem::take(&mut self.state.lock().unwrap().tasks);
+        for task in tasks.into_values() {
+            task.shutdown();
+        }
+    }
+}
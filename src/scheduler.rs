@@ -1,87 +1,441 @@
-//! Simple task scheduler
+//! Work-stealing task scheduler
 
-use std::sync::{Arc, Mutex};
-use std::collections::VecDeque;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::runtime::driver::Driver;
+use crate::task::Priority;
+use crossbeam::deque::{Injector, Steal, Stealer, Worker};
+use std::cell::RefCell;
+use std::ptr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 
 /// Task type for the scheduler
-pub type Task = Box<dyn FnOnce() + Send>;
+pub type Task = crate::task::RawTask;
 
-/// Simple scheduler with round-robin assignment
+/// How often (in ticks) a worker checks the injector before its own queue,
+/// so tasks scheduled from outside can't be starved by a busy local queue
+const INJECTOR_CHECK_INTERVAL: u32 = 61;
+
+/// How often (in ticks) a worker serves background tasks first, guaranteeing
+/// them a minimum share of the worker while higher priorities are busy
+const BACKGROUND_SHARE_INTERVAL: u32 = 8;
+
+/// Order in which priorities are normally served
+const PRIORITY_ORDER: [Priority; Priority::COUNT] = [Priority::High, Priority::Normal, Priority::Background];
+
+/// Order used on background ticks
+const BACKGROUND_FIRST_ORDER: [Priority; Priority::COUNT] = [Priority::Background, Priority::High, Priority::Normal];
+
+/// One queue per priority level, indexed by `Priority::index`
+type PerPriority<T> = [T; Priority::COUNT];
+
+thread_local! {
+    /// Local queue owned by the worker running on this thread, if any
+    static LOCAL: RefCell<Option<LocalQueue>> = const { RefCell::new(None) };
+}
+
+/// Work-stealing scheduler
+///
+/// Each worker owns a lock-free deque per priority; idle workers steal half
+/// of a victim's queue at a time, and tasks scheduled from outside go through
+/// an injector. Higher priorities are served first, except that every few
+/// ticks background tasks go ahead so they can't be starved.
 pub struct Scheduler {
-    /// Global task queue
-    global: Arc<Mutex<VecDeque<Task>>>,
+    /// Queues for tasks scheduled from outside the workers
+    injectors: PerPriority<Injector<Task>>,
     
-    /// Per-worker local queues
-    workers: Vec<Arc<Mutex<VecDeque<Task>>>>,
+    /// Stealing ends of every worker's local queues
+    stealers: Vec<PerPriority<Stealer<Task>>>,
     
-    /// Next worker for round-robin assignment
-    next_worker: AtomicUsize,
+    /// Owning ends of local queues not currently claimed by a thread
+    locals: Mutex<Vec<Option<Core>>>,
     
     /// Number of workers
     num_workers: usize,
+    
+    /// Per-worker parkers
+    parkers: Vec<Arc<Parker>>,
+    
+    /// I/O and timer drivers, turned by idle workers
+    driver: Option<Arc<Driver>>,
+    
+    /// Workers that are parked or about to park
+    idle: Mutex<Vec<usize>>,
+    
+    /// Length of `idle`, readable without taking the lock
+    num_idle: AtomicUsize,
+}
+
+/// A worker's local queue, installed in the thread that claimed it
+struct LocalQueue {
+    /// Scheduler the queue belongs to; only compared, never dereferenced
+    scheduler: *const Scheduler,
+    worker_id: usize,
+    core: Core,
+}
+
+/// Owning ends of a worker's queues, handed between threads as a unit
+struct Core {
+    queues: PerPriority<Worker<Task>>,
+    
+    /// Scheduling decisions made so far, for the periodic fairness checks
+    tick: u32,
+}
+
+/// Releases a claimed local queue when dropped
+pub(crate) struct LocalGuard<'a> {
+    scheduler: &'a Scheduler,
+    prev: Option<LocalQueue>,
+    claimed: bool,
+}
+
+/// Blocks an idle thread until it is explicitly unparked
+///
+/// Unparking bumps an epoch rather than setting a flag, so every thread parked
+/// on the same parker wakes up and an unpark that races ahead of `park` is
+/// never lost.
+///
+/// A parker given a driver blocks on the driver instead whenever no other
+/// thread is, so an idle worker also waits for I/O and timers.
+pub(crate) struct Parker {
+    epoch: AtomicU64,
+    lock: Mutex<()>,
+    condvar: Condvar,
+    driver: Option<Arc<Driver>>,
+    
+    /// Set while the parked thread blocks on the driver
+    on_driver: AtomicBool,
 }
 
 impl Scheduler {
     /// Create new scheduler
     pub fn new(num_workers: usize) -> Self {
-        let mut workers = Vec::with_capacity(num_workers);
+        Self::with_driver(num_workers, None)
+    }
+    
+    /// Create a scheduler whose idle workers turn `driver`
+    pub(crate) fn with_driver(num_workers: usize, driver: Option<Arc<Driver>>) -> Self {
+        let mut locals = Vec::with_capacity(num_workers);
+        let mut stealers = Vec::with_capacity(num_workers);
+        let mut parkers = Vec::with_capacity(num_workers);
         for _ in 0..num_workers {
-            workers.push(Arc::new(Mutex::new(VecDeque::new())));
+            let queues: PerPriority<Worker<Task>> = std::array::from_fn(|_| Worker::new_fifo());
+            stealers.push(std::array::from_fn(|i| queues[i].stealer()));
+            locals.push(Some(Core { queues, tick: 0 }));
+            parkers.push(Arc::new(Parker::with_driver(driver.clone())));
         }
         
         Self {
-            global: Arc::new(Mutex::new(VecDeque::new())),
-            workers,
-            next_worker: AtomicUsize::new(0),
+            injectors: std::array::from_fn(|_| Injector::new()),
+            stealers,
+            locals: Mutex::new(locals),
             num_workers,
+            parkers,
+            driver,
+            idle: Mutex::new(Vec::with_capacity(num_workers)),
+            num_idle: AtomicUsize::new(0),
         }
     }
     
     /// Schedule a task
+    ///
+    /// From a worker thread the task goes onto that worker's local queue for
+    /// its priority, otherwise onto the matching injector.
     pub fn schedule(&self, task: Task) {
-        let worker_id = self.next_worker.fetch_add(1, Ordering::Relaxed) % self.num_workers;
+        let priority = task.priority().index();
+        let task = LOCAL.with(|local| match local.borrow().as_ref() {
+            Some(local) if ptr::eq(local.scheduler, self) => {
+                local.core.queues[priority].push(task);
+                None
+            }
+            _ => Some(task),
+        });
         
-        // Try local queue first
-        if let Ok(mut queue) = self.workers[worker_id].try_lock() {
-            queue.push_back(task);
-        } else {
-            // Fallback to global queue
-            self.global.lock().unwrap().push_back(task);
+        if let Some(task) = task {
+            self.injectors[priority].push(task);
+        }
+        
+        self.notify_one();
+    }
+    
+    /// Install worker `worker_id`'s local queue on the current thread
+    ///
+    /// Returns `None` if another thread holds the queue, e.g. a thread that
+    /// is about to leave `block_in_place` and take it back. The worker's
+    /// parker is unparked once the queue is given back.
+    pub(crate) fn try_claim(&self, worker_id: usize) -> Option<LocalGuard<'_>> {
+        let already_claimed = LOCAL.with(|local| {
+            matches!(local.borrow().as_ref(), Some(local)
+                if ptr::eq(local.scheduler, self) && local.worker_id == worker_id)
+        });
+        
+        if already_claimed {
+            return Some(LocalGuard {
+                scheduler: self,
+                prev: None,
+                claimed: false,
+            });
+        }
+        
+        let core = self.locals.lock().unwrap()[worker_id].take()?;
+        let prev = LOCAL.with(|local| {
+            local.borrow_mut().replace(LocalQueue {
+                scheduler: self,
+                worker_id,
+                core,
+            })
+        });
+        
+        Some(LocalGuard {
+            scheduler: self,
+            prev,
+            claimed: true,
+        })
+    }
+    
+    /// Hand the current thread's local queue back so another thread can claim it
+    ///
+    /// Returns the worker id to pass to `reclaim_local` afterwards.
+    pub(crate) fn release_local(&self) -> Option<usize> {
+        let local = LOCAL.with(|local| {
+            let mut local = local.borrow_mut();
+            match local.as_ref() {
+                Some(queue) if ptr::eq(queue.scheduler, self) => local.take(),
+                _ => None,
+            }
+        })?;
+        
+        let worker_id = local.worker_id;
+        self.locals.lock().unwrap()[worker_id] = Some(local.core);
+        self.parkers[worker_id].unpark();
+        Some(worker_id)
+    }
+    
+    /// Take back a local queue given up with `release_local`
+    ///
+    /// Waits until whichever thread claimed it in the meantime lets go.
+    pub(crate) fn reclaim_local(&self, worker_id: usize) {
+        // Returning the queue unparks its worker's parker
+        let parker = &self.parkers[worker_id];
+        let core = loop {
+            let epoch = parker.epoch();
+            if let Some(core) = self.locals.lock().unwrap()[worker_id].take() {
+                break core;
+            }
+            parker.park(epoch);
+        };
+        
+        LOCAL.with(|local| {
+            *local.borrow_mut() = Some(LocalQueue {
+                scheduler: self,
+                worker_id,
+                core,
+            });
+        });
+    }
+    
+    /// Wake one parked worker, if any, to pick up new work
+    fn notify_one(&self) {
+        // Pairs with the fence in `park` so either we see the idle worker or it sees our task
+        std::sync::atomic::fence(Ordering::SeqCst);
+        if self.num_idle.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        
+        let worker_id = {
+            let mut idle = self.idle.lock().unwrap();
+            let worker_id = idle.pop();
+            self.num_idle.store(idle.len(), Ordering::SeqCst);
+            worker_id
+        };
+        
+        if let Some(worker_id) = worker_id {
+            self.parkers[worker_id].unpark();
+        }
+    }
+    
+    /// Wake a specific worker, whether or not it is parked
+    pub(crate) fn unpark(&self, worker_id: usize) {
+        self.parkers[worker_id].unpark();
+    }
+    
+    /// Drop every queued task, breaking the reference cycle between the
+    /// scheduler and the tasks queued in it
+    ///
+    /// Only called once the runtime has shut down and no worker runs tasks.
+    pub(crate) fn clear(&self) {
+        for injector in &self.injectors {
+            while !matches!(injector.steal(), Steal::Empty) {}
+        }
+        for stealer in self.stealers.iter().flatten() {
+            while !matches!(stealer.steal(), Steal::Empty) {}
+        }
+    }
+    
+    /// Wake every worker, e.g. so they notice shutdown
+    pub(crate) fn unpark_all(&self) {
+        for parker in &self.parkers {
+            parker.unpark();
+        }
+    }
+    
+    /// Dispatch ready I/O and expired timers, unless another thread is turning the driver
+    pub(crate) fn poll_driver(&self) {
+        if let Some(turn) = self.driver.as_ref().and_then(|driver| driver.try_turn()) {
+            turn.poll();
+        }
+    }
+    
+    /// Wake the thread blocked on the driver, e.g. to wait for an earlier timer
+    #[cfg(feature = "time")]
+    pub(crate) fn unpark_driver(&self) {
+        if let Some(driver) = &self.driver {
+            driver.unpark();
+        }
+    }
+    
+    /// Parker used by the given worker
+    pub(crate) fn parker(&self, worker_id: usize) -> &Arc<Parker> {
+        &self.parkers[worker_id]
+    }
+    
+    /// Park a worker until new work is scheduled or it is unparked
+    ///
+    /// `epoch` must be read from the worker's parker before it last checked
+    /// for work. Returns immediately if work shows up while the worker
+    /// advertises itself as idle.
+    pub(crate) fn park(&self, worker_id: usize, epoch: u64) {
+        let parker = &self.parkers[worker_id];
+        
+        {
+            let mut idle = self.idle.lock().unwrap();
+            idle.push(worker_id);
+            self.num_idle.store(idle.len(), Ordering::SeqCst);
+        }
+        std::sync::atomic::fence(Ordering::SeqCst);
+        
+        if !self.is_empty() {
+            self.unidle(worker_id);
+            return;
         }
+        
+        parker.park(epoch);
+        self.unidle(worker_id);
+    }
+    
+    /// Remove a worker from the idle list if a notifier hasn't already
+    fn unidle(&self, worker_id: usize) {
+        let mut idle = self.idle.lock().unwrap();
+        if let Some(pos) = idle.iter().position(|&id| id == worker_id) {
+            idle.swap_remove(pos);
+            self.num_idle.store(idle.len(), Ordering::SeqCst);
+        }
+    }
+    
+    /// Check every queue for work
+    fn is_empty(&self) -> bool {
+        self.injectors.iter().all(|injector| injector.is_empty())
+            && self.stealers.iter().flatten().all(|stealer| stealer.is_empty())
     }
     
     /// Find work for a worker
     pub fn find_work(&self, worker_id: usize) -> Option<Task> {
-        // 1. Check local queue first
-        if let Ok(mut queue) = self.workers[worker_id].try_lock() {
-            if let Some(task) = queue.pop_front() {
+        LOCAL.with(|local| match local.borrow_mut().as_mut() {
+            Some(local) if ptr::eq(local.scheduler, self) => self.find_local(local),
+            _ => self.find_unowned(worker_id),
+        })
+    }
+    
+    /// Order to serve priorities in on the given tick
+    fn priority_order(tick: u32) -> PerPriority<Priority> {
+        if tick.is_multiple_of(BACKGROUND_SHARE_INTERVAL) {
+            BACKGROUND_FIRST_ORDER
+        } else {
+            PRIORITY_ORDER
+        }
+    }
+    
+    /// Find work for a thread that owns a local queue
+    fn find_local(&self, local: &mut LocalQueue) -> Option<Task> {
+        let core = &mut local.core;
+        core.tick = core.tick.wrapping_add(1);
+        let check_injector_first = core.tick.is_multiple_of(INJECTOR_CHECK_INTERVAL);
+        let order = Self::priority_order(core.tick);
+        
+        // Work this worker can reach without contending with others
+        for priority in order {
+            let queue = &core.queues[priority.index()];
+            let injector = &self.injectors[priority.index()];
+            
+            // 1. Periodically give injected tasks priority
+            if check_injector_first {
+                if let Some(task) = steal(|| injector.steal_batch_and_pop(queue)) {
+                    return Some(task);
+                }
+            }
+            
+            // 2. Check local queue
+            if let Some(task) = queue.pop() {
+                return Some(task);
+            }
+            
+            // 3. Check injector
+            if let Some(task) = steal(|| injector.steal_batch_and_pop(queue)) {
                 return Some(task);
             }
         }
         
-        // 2. Check global queue
-        if let Ok(mut queue) = self.global.try_lock() {
-            if let Some(task) = queue.pop_front() {
+        // 4. Steal half of another worker's queue
+        for priority in order {
+            let queue = &core.queues[priority.index()];
+            let task = self.steal_work(local.worker_id, |stealers| {
+                stealers[priority.index()].steal_batch_and_pop(queue)
+            });
+            
+            if task.is_some() {
+                return task;
+            }
+        }
+        
+        None
+    }
+    
+    /// Find work for a thread without a local queue to steal into
+    fn find_unowned(&self, worker_id: usize) -> Option<Task> {
+        for priority in PRIORITY_ORDER {
+            let index = priority.index();
+            
+            if let Some(task) = steal(|| self.injectors[index].steal()) {
+                return Some(task);
+            }
+            
+            if let Some(task) = steal(|| self.stealers[worker_id][index].steal()) {
+                return Some(task);
+            }
+            
+            if let Some(task) = self.steal_work(worker_id, |stealers| stealers[index].steal()) {
                 return Some(task);
             }
         }
         
-        // 3. Try stealing from other workers
-        self.steal_work(worker_id)
+        None
     }
     
-    /// Steal work from other workers
-    fn steal_work(&self, worker_id: usize) -> Option<Task> {
+    /// Steal work from other workers, starting at a random victim
+    fn steal_work(
+        &self,
+        worker_id: usize,
+        steal_from: impl Fn(&PerPriority<Stealer<Task>>) -> Steal<Task>,
+    ) -> Option<Task> {
+        let start = fastrand::usize(..self.num_workers);
+        
         for i in 0..self.num_workers {
-            if i == worker_id {
+            let victim = (start + i) % self.num_workers;
+            if victim == worker_id {
                 continue;
             }
             
-            if let Ok(mut queue) = self.workers[i].try_lock() {
-                if let Some(task) = queue.pop_back() {
-                    return Some(task);
-                }
+            if let Some(task) = steal(|| steal_from(&self.stealers[victim])) {
+                return Some(task);
             }
         }
         
@@ -90,22 +444,95 @@ impl Scheduler {
     
     /// Check if there's any work available
     pub fn has_work(&self) -> bool {
-        // Check global queue
-        if let Ok(queue) = self.global.try_lock() {
-            if !queue.is_empty() {
-                return true;
-            }
+        !self.is_empty()
+    }
+}
+
+/// Retry a steal operation until it either succeeds or finds the queue empty
+fn steal(mut attempt: impl FnMut() -> Steal<Task>) -> Option<Task> {
+    loop {
+        match attempt() {
+            Steal::Success(task) => return Some(task),
+            Steal::Empty => return None,
+            Steal::Retry => continue,
+        }
+    }
+}
+
+impl Drop for LocalGuard<'_> {
+    fn drop(&mut self) {
+        if !self.claimed {
+            return;
         }
         
-        // Check worker queues
-        for worker in &self.workers {
-            if let Ok(queue) = worker.try_lock() {
-                if !queue.is_empty() {
-                    return true;
-                }
+        let prev = self.prev.take();
+        let local = LOCAL.with(|local| std::mem::replace(&mut *local.borrow_mut(), prev));
+        
+        // The queue may have been released already by `release_local`
+        if let Some(local) = local {
+            if ptr::eq(local.scheduler, self.scheduler) {
+                self.scheduler.locals.lock().unwrap()[local.worker_id] = Some(local.core);
+                
+                // Wake a thread waiting to claim the queue
+                self.scheduler.parkers[local.worker_id].unpark();
+            }
+        }
+    }
+}
+
+impl Parker {
+    /// Create a parker with no pending unpark
+    pub(crate) fn new() -> Self {
+        Self::with_driver(None)
+    }
+    
+    /// Create a parker that blocks on `driver` when it can
+    pub(crate) fn with_driver(driver: Option<Arc<Driver>>) -> Self {
+        Self {
+            epoch: AtomicU64::new(0),
+            lock: Mutex::new(()),
+            condvar: Condvar::new(),
+            driver,
+            on_driver: AtomicBool::new(false),
+        }
+    }
+    
+    /// Current epoch; read it before checking the wake-up condition
+    pub(crate) fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::SeqCst)
+    }
+    
+    /// Block until the epoch moves past `epoch`
+    ///
+    /// When this thread gets to turn the driver it may also return early,
+    /// after an I/O event or a timer, so callers re-check their condition.
+    pub(crate) fn park(&self, epoch: u64) {
+        if let Some(turn) = self.driver.as_ref().and_then(|driver| driver.try_turn()) {
+            // Pairs with `unpark`: either it sees us on the driver or we see the new epoch
+            self.on_driver.store(true, Ordering::SeqCst);
+            if self.epoch.load(Ordering::SeqCst) == epoch {
+                turn.park();
             }
+            self.on_driver.store(false, Ordering::SeqCst);
+            return;
+        }
+        
+        let mut guard = self.lock.lock().unwrap();
+        while self.epoch.load(Ordering::SeqCst) == epoch {
+            guard = self.condvar.wait(guard).unwrap();
         }
+    }
+    
+    /// Wake every thread parked on this parker
+    pub(crate) fn unpark(&self) {
+        let _guard = self.lock.lock().unwrap();
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+        self.condvar.notify_all();
         
-        false
+        if self.on_driver.load(Ordering::SeqCst) {
+            if let Some(driver) = &self.driver {
+                driver.unpark();
+            }
+        }
     }
 }
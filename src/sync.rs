@@ -20,6 +20,7 @@ impl<T> Mutex<T> {
     
     /// Lock the mutex
     pub async fn lock(&self) -> parking_lot::MutexGuard<'_, T> {
+        crate::coop::consume_budget().await;
         self.inner.lock()
     }
 }
@@ -34,11 +35,13 @@ impl<T> RwLock<T> {
     
     /// Read lock
     pub async fn read(&self) -> parking_lot::RwLockReadGuard<'_, T> {
+        crate::coop::consume_budget().await;
         self.inner.read()
     }
     
     /// Write lock
     pub async fn write(&self) -> parking_lot::RwLockWriteGuard<'_, T> {
+        crate::coop::consume_budget().await;
         self.inner.write()
     }
 }
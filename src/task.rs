@@ -1,31 +1,174 @@
 //! Task management and join handles
 
+use futures::task::AtomicWaker;
+use std::any::Any;
 use std::future::Future;
+use std::panic::Location;
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 
+mod builder;
+mod id;
+mod join_set;
+mod local;
+mod raw;
+mod scope;
+mod task_local;
+
+pub use builder::Builder;
+pub use id::{id, try_id, Id};
+pub use join_set::JoinSet;
+pub use local::{spawn_local, LocalSet, RunUntil};
+pub use scope::{scope, Scope};
+pub use task_local::{AccessError, LocalKey, TaskLocalFuture};
+pub use raw::RawTask;
+pub use crate::coop::{consume_budget, unconstrained, Unconstrained};
+
+pub(crate) use id::{with_id, Meta, Tracked};
+pub(crate) use raw::Header;
+
 /// Join handle for spawned tasks
+///
+/// Dropping the handle detaches the task; use [`JoinHandle::abort`] to cancel it.
 pub struct JoinHandle<T> {
     control: Arc<TaskControl<T>>,
+    abort: AbortHandle,
+    meta: Arc<Meta>,
+}
+
+/// Cloneable handle that cancels a task without being able to await it
+#[derive(Clone)]
+pub struct AbortHandle {
+    raw: Option<Arc<dyn RemoteAbort>>,
+}
+
+/// A task that can be cancelled from another thread
+pub(crate) trait RemoteAbort: Send + Sync {
+    /// Request cancellation; the task is dropped the next time it would be polled
+    fn abort(self: Arc<Self>);
+}
+
+/// Completes a task's handle with a cancellation error if its future is
+/// dropped before producing a result
+pub(crate) struct CompletionGuard<T> {
+    control: Option<Arc<TaskControl<T>>>,
 }
 
 /// Task control for managing task lifecycle
 pub struct TaskControl<T> {
     result: Mutex<Option<Result<T, JoinError>>>,
     completed: std::sync::atomic::AtomicBool,
+    
+    /// Waker of the task awaiting the `JoinHandle`, woken on completion
+    waker: AtomicWaker,
+}
+
+/// Scheduling priority of a spawned task
+///
+/// Workers serve higher priorities first, but background tasks are still
+/// guaranteed a minimum share of every worker so they can't be starved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[non_exhaustive]
+pub enum Priority {
+    /// Latency-sensitive work such as health checks and control messages
+    High,
+    
+    /// The default priority
+    #[default]
+    Normal,
+    
+    /// Bulk work that may wait while anything else is runnable
+    Background,
 }
 
 /// Join error
-#[derive(Debug, Clone)]
 pub struct JoinError {
-    message: String,
+    repr: Repr,
+}
+
+enum Repr {
+    /// The task was aborted before it completed
+    Cancelled,
+    
+    /// The task panicked; the mutex only makes the payload `Sync`
+    Panic(Mutex<Box<dyn Any + Send + 'static>>),
+    
+    /// The task could not run to completion
+    Failed(String),
+}
+
+/// Run a blocking closure on a dedicated thread pool
+///
+/// Use this for synchronous I/O or CPU-heavy work that would otherwise stall a
+/// worker thread. The pool grows on demand up to
+/// [`Builder::max_blocking_threads`](crate::runtime::Builder::max_blocking_threads).
+#[track_caller]
+pub fn spawn_blocking<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    crate::runtime::spawn_blocking(f)
+}
+
+/// Run a blocking closure in place on the current worker thread
+///
+/// The worker's queued tasks are handed to a replacement thread for the
+/// duration of `f`, so other tasks keep making progress. Outside of a
+/// multi-threaded runtime worker `f` simply runs on the current thread.
+pub fn block_in_place<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    crate::runtime::block_in_place(f)
 }
 
 impl<T> JoinHandle<T> {
-    /// Create new join handle
+    /// Create new join handle, assigning the task a fresh id
+    #[track_caller]
     pub fn new(control: Arc<TaskControl<T>>) -> Self {
-        Self { control }
+        Self::with_meta(control, Meta::new(None, Location::caller()))
+    }
+    
+    /// Create a join handle for the task described by `meta`
+    pub(crate) fn with_meta(control: Arc<TaskControl<T>>, meta: Arc<Meta>) -> Self {
+        Self {
+            control,
+            abort: AbortHandle { raw: None },
+            meta,
+        }
+    }
+    
+    /// Create a join handle that can abort the task behind it
+    pub(crate) fn with_abort(control: Arc<TaskControl<T>>, raw: Arc<dyn RemoteAbort>, meta: Arc<Meta>) -> Self {
+        Self {
+            control,
+            abort: AbortHandle { raw: Some(raw) },
+            meta,
+        }
+    }
+    
+    /// Create a handle that has already resolved with `error`
+    pub(crate) fn failed(error: JoinError, meta: Arc<Meta>) -> Self {
+        let control = TaskControl::new();
+        control.complete(Err(error));
+        Self::with_meta(control, meta)
+    }
+    
+    /// Id of the task, as returned by [`id()`] from inside it
+    pub fn id(&self) -> Id {
+        self.meta.id
+    }
+    
+    /// Name given to the task with [`Builder::name`], if any
+    pub fn name(&self) -> Option<&str> {
+        self.meta.name.as_deref()
+    }
+    
+    /// Source location of the call that spawned the task
+    pub fn spawn_location(&self) -> &'static Location<'static> {
+        self.meta.location
     }
     
     /// Try to get result without blocking
@@ -41,16 +184,68 @@ impl<T> JoinHandle<T> {
     pub fn is_finished(&self) -> bool {
         self.control.completed.load(std::sync::atomic::Ordering::Acquire)
     }
+    
+    /// Cancel the task
+    ///
+    /// The task's future is dropped the next time it would be polled and the
+    /// handle resolves to an error for which [`JoinError::is_cancelled`]
+    /// returns `true`. Tasks that already completed are unaffected, as are
+    /// blocking tasks, which can't be interrupted.
+    pub fn abort(&self) {
+        self.abort.abort();
+    }
+    
+    /// Get a cloneable handle that can abort this task
+    pub fn abort_handle(&self) -> AbortHandle {
+        self.abort.clone()
+    }
+}
+
+impl AbortHandle {
+    /// Cancel the task, as with [`JoinHandle::abort`]
+    pub fn abort(&self) {
+        if let Some(raw) = &self.raw {
+            raw.clone().abort();
+        }
+    }
+}
+
+impl RemoteAbort for AbortHandle {
+    fn abort(self: Arc<Self>) {
+        AbortHandle::abort(&self);
+    }
+}
+
+impl std::fmt::Debug for AbortHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AbortHandle").finish_non_exhaustive()
+    }
+}
+
+impl<T> std::fmt::Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JoinHandle")
+            .field("id", &self.meta.id)
+            .field("name", &self.meta.name)
+            .field("location", &self.meta.location)
+            .finish_non_exhaustive()
+    }
 }
 
 impl<T> Future for JoinHandle<T> {
     type Output = Result<T, JoinError>;
     
-    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         if let Some(result) = self.try_result() {
-            Poll::Ready(result)
-        } else {
-            Poll::Pending
+            return Poll::Ready(result);
+        }
+        
+        // Register before re-checking so a completion in between isn't missed
+        self.control.waker.register(cx.waker());
+        
+        match self.try_result() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
         }
     }
 }
@@ -61,13 +256,53 @@ impl<T> TaskControl<T> {
         Arc::new(Self {
             result: Mutex::new(None),
             completed: std::sync::atomic::AtomicBool::new(false),
+            waker: AtomicWaker::new(),
         })
     }
     
-    /// Complete the task with result
+    /// Complete the task with result, waking whoever awaits the handle
     pub fn complete(&self, result: Result<T, JoinError>) {
         *self.result.lock().unwrap() = Some(result);
         self.completed.store(true, std::sync::atomic::Ordering::Release);
+        self.waker.wake();
+    }
+}
+
+impl Priority {
+    /// Number of priority levels
+    pub(crate) const COUNT: usize = 3;
+    
+    /// Index of this priority's run queues
+    pub(crate) fn index(self) -> usize {
+        match self {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Background => 2,
+        }
+    }
+}
+
+impl<T> CompletionGuard<T> {
+    /// Guard `control` until `complete` is called
+    pub(crate) fn new(control: Arc<TaskControl<T>>) -> Self {
+        Self {
+            control: Some(control),
+        }
+    }
+    
+    /// Complete the task with its result, disarming the guard
+    pub(crate) fn complete(mut self, result: Result<T, JoinError>) {
+        if let Some(control) = self.control.take() {
+            control.complete(result);
+        }
+    }
+}
+
+impl<T> Drop for CompletionGuard<T> {
+    fn drop(&mut self) {
+        if let Some(control) = self.control.take() {
+            control.complete(Err(JoinError::cancelled()));
+        }
     }
 }
 
@@ -75,15 +310,95 @@ impl JoinError {
     /// Create new join error
     pub fn new(message: impl Into<String>) -> Self {
         Self {
-            message: message.into(),
+            repr: Repr::Failed(message.into()),
+        }
+    }
+    
+    /// Error for a task that was aborted
+    pub(crate) fn cancelled() -> Self {
+        Self {
+            repr: Repr::Cancelled,
+        }
+    }
+    
+    /// Error for a task whose future panicked with `payload`
+    pub(crate) fn panic(payload: Box<dyn Any + Send + 'static>) -> Self {
+        Self {
+            repr: Repr::Panic(Mutex::new(payload)),
+        }
+    }
+    
+    /// Whether the task was cancelled before it completed
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.repr, Repr::Cancelled)
+    }
+    
+    /// Whether the task panicked
+    pub fn is_panic(&self) -> bool {
+        matches!(self.repr, Repr::Panic(_))
+    }
+    
+    /// Consume the error, returning the panic payload
+    ///
+    /// Pass the payload to [`std::panic::resume_unwind`] to propagate the panic.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the task did not panic; check [`JoinError::is_panic`] first
+    /// or use [`JoinError::try_into_panic`].
+    pub fn into_panic(self) -> Box<dyn Any + Send + 'static> {
+        self.try_into_panic()
+            .expect("`JoinError::into_panic` called on a task that did not panic")
+    }
+    
+    /// Consume the error, returning the panic payload if the task panicked
+    pub fn try_into_panic(self) -> Result<Box<dyn Any + Send + 'static>, JoinError> {
+        match self.repr {
+            Repr::Panic(payload) => Ok(payload.into_inner().unwrap_or_else(|e| e.into_inner())),
+            repr => Err(Self { repr }),
+        }
+    }
+}
+
+/// Best-effort message of a panic payload
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    if let Some(message) = payload.downcast_ref::<&'static str>() {
+        Some(message)
+    } else {
+        payload.downcast_ref::<String>().map(String::as_str)
+    }
+}
+
+impl std::fmt::Debug for JoinError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.repr {
+            Repr::Cancelled => write!(f, "JoinError::Cancelled"),
+            Repr::Panic(_) => write!(f, "JoinError::Panic(..)"),
+            Repr::Failed(message) => write!(f, "JoinError::Failed({:?})", message),
         }
     }
 }
 
 impl std::fmt::Display for JoinError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.message)
+        match &self.repr {
+            Repr::Cancelled => write!(f, "task was cancelled"),
+            Repr::Panic(payload) => {
+                let payload = payload.lock().unwrap_or_else(|e| e.into_inner());
+                match panic_message(&**payload) {
+                    Some(message) => write!(f, "task panicked: {}", message),
+                    None => write!(f, "task panicked"),
+                }
+            }
+            Repr::Failed(message) => write!(f, "{}", message),
+        }
     }
 }
 
 impl std::error::Error for JoinError {}
+
+impl From<JoinError> for std::io::Error {
+    fn from(err: JoinError) -> Self {
+        std::io::Error::other(err)
+    }
+}
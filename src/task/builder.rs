@@ -0,0 +1,102 @@
+//! Configuring tasks before they are spawned
+
+use super::{local, JoinHandle, Meta, Priority};
+use crate::runtime::Handle;
+use std::future::Future;
+use std::panic::Location;
+
+/// Task factory for setting a name and priority before spawning
+///
+/// Names show up in a [`JoinHandle`]'s `Debug` output and through
+/// [`JoinHandle::name`], which makes it easier to tell tasks apart when
+/// debugging.
+///
+/// ```no_run
+/// use cycle::task;
+///
+/// cycle::block_on(async {
+///     let handle = task::Builder::new()
+///         .name("conn-handler")
+///         .spawn(async { task::id() });
+///
+///     assert_eq!(handle.name(), Some("conn-handler"));
+///     let id = handle.id();
+///     assert_eq!(handle.await.unwrap(), id);
+/// });
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Builder {
+    name: Option<String>,
+    priority: Priority,
+}
+
+impl Builder {
+    /// Create a builder for an unnamed task with normal priority
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name the task
+    pub fn name(&mut self, name: impl Into<String>) -> &mut Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Set the task's scheduling priority
+    ///
+    /// Ignored by [`Builder::spawn_local`] and [`Builder::spawn_blocking`].
+    pub fn priority(&mut self, priority: Priority) -> &mut Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Spawn the task on the current runtime, or the global runtime outside of one
+    #[track_caller]
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let meta = self.meta(Location::caller());
+        crate::runtime::handle::with_current(|handle| handle.spawn_task(meta, self.priority, future))
+    }
+
+    /// Spawn the task on the runtime behind `handle`
+    #[track_caller]
+    pub fn spawn_on<F>(&self, future: F, handle: &Handle) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        handle.spawn_task(self.meta(Location::caller()), self.priority, future)
+    }
+
+    /// Spawn a `!Send` task on the [`LocalSet`](super::LocalSet) currently being driven
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a running `LocalSet`.
+    #[track_caller]
+    pub fn spawn_local<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        local::spawn_local_task(self.meta(Location::caller()), future)
+    }
+
+    /// Run a blocking closure on the blocking pool of the current runtime
+    #[track_caller]
+    pub fn spawn_blocking<F, R>(&self, f: F) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let meta = self.meta(Location::caller());
+        crate::runtime::handle::with_current(|handle| handle.spawn_blocking_task(meta, f))
+    }
+
+    fn meta(&self, location: &'static Location<'static>) -> std::sync::Arc<Meta> {
+        Meta::new(self.name.clone(), location)
+    }
+}
@@ -0,0 +1,114 @@
+//! Task ids, names and spawn locations
+
+use std::cell::Cell;
+use std::fmt;
+use std::future::Future;
+use std::num::NonZeroU64;
+use std::panic::Location;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Source of task ids; starts at one so ids fit in a `NonZeroU64`
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    /// Id of the task being polled on this thread
+    static CURRENT: Cell<Option<Id>> = const { Cell::new(None) };
+}
+
+/// Unique identifier of a spawned task
+///
+/// Ids are never reused while the process runs, so they stay meaningful in
+/// logs after the task has completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Id(NonZeroU64);
+
+/// What is known about a task from the moment it is spawned
+pub(crate) struct Meta {
+    pub(crate) id: Id,
+    pub(crate) name: Option<String>,
+    pub(crate) location: &'static Location<'static>,
+}
+
+pin_project_lite::pin_project! {
+    /// Makes a task's id current on the thread while its future is polled
+    pub(crate) struct Tracked<F> {
+        id: Id,
+        #[pin]
+        future: F,
+    }
+}
+
+/// Id of the task currently running
+///
+/// # Panics
+///
+/// Panics if called from outside of a task; see [`try_id`].
+pub fn id() -> Id {
+    try_id().expect("`task::id` called from outside of a task")
+}
+
+/// Id of the task currently running, or `None` outside of a task
+///
+/// Blocking closures run by [`spawn_blocking`](super::spawn_blocking) count
+/// as tasks; the future passed to `block_on` does not.
+pub fn try_id() -> Option<Id> {
+    CURRENT.with(Cell::get)
+}
+
+/// Run `f` with `id` as the current task id
+pub(crate) fn with_id<R>(id: Id, f: impl FnOnce() -> R) -> R {
+    struct Reset(Option<Id>);
+
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            CURRENT.with(|current| current.set(self.0));
+        }
+    }
+
+    let prev = CURRENT.with(|current| current.replace(Some(id)));
+    let _reset = Reset(prev);
+    f()
+}
+
+impl Id {
+    fn next() -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        Self(NonZeroU64::new(id).expect("task ids exhausted"))
+    }
+}
+
+impl fmt::Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Meta {
+    /// Metadata for a new task spawned at `location`, with a fresh id
+    pub(crate) fn new(name: Option<String>, location: &'static Location<'static>) -> Arc<Self> {
+        Arc::new(Self {
+            id: Id::next(),
+            name,
+            location,
+        })
+    }
+}
+
+impl<F> Tracked<F> {
+    pub(crate) fn new(id: Id, future: F) -> Self {
+        Self { id, future }
+    }
+}
+
+impl<F: Future> Future for Tracked<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = self.project();
+        let future = this.future;
+        with_id(*this.id, || future.poll(cx))
+    }
+}
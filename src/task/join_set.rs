@@ -0,0 +1,141 @@
+//! Owning a dynamic group of spawned tasks
+
+use super::{spawn_local, JoinError, JoinHandle};
+use crate::runtime::Runtime;
+use futures::stream::{FuturesUnordered, StreamExt};
+use futures::FutureExt;
+use std::future::Future;
+
+/// A collection of spawned tasks, awaited in the order they complete
+///
+/// All tasks must have the same output type. Dropping the set aborts every
+/// task still in it; use [`JoinSet::detach_all`] to let them keep running.
+///
+/// ```no_run
+/// use cycle::task::JoinSet;
+///
+/// cycle::block_on(async {
+///     let mut set = JoinSet::new();
+///     for i in 0..10 {
+///         set.spawn(async move { i * 2 });
+///     }
+///
+///     let mut total = 0;
+///     while let Some(result) = set.join_next().await {
+///         total += result.unwrap();
+///     }
+///     assert_eq!(total, 90);
+/// });
+/// ```
+pub struct JoinSet<T> {
+    tasks: FuturesUnordered<JoinHandle<T>>,
+}
+
+impl<T> JoinSet<T> {
+    /// Create an empty set
+    pub fn new() -> Self {
+        Self {
+            tasks: FuturesUnordered::new(),
+        }
+    }
+
+    /// Number of tasks in the set, including finished ones not yet joined
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Whether the set has no tasks
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Add an already spawned task to the set
+    pub fn insert(&mut self, handle: JoinHandle<T>) {
+        self.tasks.push(handle);
+    }
+
+    /// Spawn a task on the current runtime and add it to the set
+    #[track_caller]
+    pub fn spawn<F>(&mut self, future: F)
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.insert(crate::spawn(future));
+    }
+
+    /// Spawn a task on `runtime` and add it to the set
+    #[track_caller]
+    pub fn spawn_on<F>(&mut self, future: F, runtime: &Runtime)
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.insert(runtime.spawn(future));
+    }
+
+    /// Spawn a `!Send` task on the current [`LocalSet`](super::LocalSet) and add it to the set
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a running `LocalSet`.
+    #[track_caller]
+    pub fn spawn_local<F>(&mut self, future: F)
+    where
+        F: Future<Output = T> + 'static,
+        T: 'static,
+    {
+        self.insert(spawn_local(future));
+    }
+
+    /// Wait for the next task to complete, returning `None` once the set is empty
+    ///
+    /// Aborted tasks show up here as errors for which
+    /// [`JoinError::is_cancelled`] returns `true`.
+    pub async fn join_next(&mut self) -> Option<Result<T, JoinError>> {
+        self.tasks.next().await
+    }
+
+    /// Take the result of a task that already completed, without waiting
+    pub fn try_join_next(&mut self) -> Option<Result<T, JoinError>> {
+        self.tasks.next().now_or_never().flatten()
+    }
+
+    /// Abort every task in the set
+    ///
+    /// The tasks stay in the set until they are joined.
+    pub fn abort_all(&mut self) {
+        for handle in self.tasks.iter() {
+            handle.abort();
+        }
+    }
+
+    /// Remove every task from the set without aborting them
+    pub fn detach_all(&mut self) {
+        self.tasks.clear();
+    }
+
+    /// Abort every task and wait for all of them to finish
+    pub async fn shutdown(&mut self) {
+        self.abort_all();
+        while self.join_next().await.is_some() {}
+    }
+}
+
+impl<T> Drop for JoinSet<T> {
+    fn drop(&mut self) {
+        self.abort_all();
+    }
+}
+
+impl<T> Default for JoinSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> std::fmt::Debug for JoinSet<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JoinSet").field("len", &self.len()).finish()
+    }
+}
@@ -0,0 +1,332 @@
+//! Running `!Send` futures on a single thread
+
+use super::{CompletionGuard, JoinError, JoinHandle, Meta, RemoteAbort, TaskControl, Tracked};
+use crate::runtime::Runtime;
+use futures::task::AtomicWaker;
+use futures::FutureExt;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::panic::{AssertUnwindSafe, Location};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+/// Maximum number of local tasks polled before yielding back to the driver
+const MAX_TASKS_PER_TICK: usize = 64;
+
+type LocalFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+thread_local! {
+    /// The `LocalSet` currently being polled on this thread
+    static CURRENT: RefCell<Option<Rc<LocalContext>>> = const { RefCell::new(None) };
+}
+
+/// A set of `!Send` tasks that all run on the same thread
+///
+/// Tasks spawned with [`spawn_local`] may hold `Rc`, `RefCell` or other
+/// thread-bound state. They only make progress while the set is being driven
+/// by [`LocalSet::block_on`], [`LocalSet::run_until`] or by awaiting the set
+/// itself, but they can await sockets, timers and join handles from the rest
+/// of the runtime as usual.
+///
+/// ```no_run
+/// use cycle::task::{self, LocalSet};
+/// use std::rc::Rc;
+///
+/// let runtime = cycle::runtime::Runtime::new()?;
+/// let local = LocalSet::new();
+///
+/// local.block_on(&runtime, async {
+///     let shared = Rc::new("not Send");
+///     let handle = task::spawn_local(async move { shared.len() });
+///     assert_eq!(handle.await.unwrap(), 8);
+/// });
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct LocalSet {
+    context: Rc<LocalContext>,
+}
+
+/// Thread-bound state of a `LocalSet`
+struct LocalContext {
+    /// Futures of all live tasks, keyed by task id
+    tasks: RefCell<HashMap<usize, LocalTask>>,
+
+    /// Id handed to the next spawned task
+    next_id: Cell<usize>,
+
+    /// State shared with the (thread-safe) task wakers
+    shared: Arc<Shared>,
+}
+
+/// A local task's future along with its waker
+struct LocalTask {
+    future: LocalFuture,
+    waker: Arc<TaskWaker>,
+}
+
+/// Cross-thread half of a `LocalSet`
+struct Shared {
+    /// Ids of tasks that were woken and need polling
+    ready: Mutex<VecDeque<usize>>,
+
+    /// Waker of whoever is driving the set
+    waker: AtomicWaker,
+}
+
+/// Waker for a single local task; only carries the task id, so it is `Send`
+struct TaskWaker {
+    id: usize,
+    queued: AtomicBool,
+    cancelled: AtomicBool,
+    shared: Arc<Shared>,
+}
+
+pin_project_lite::pin_project! {
+    /// Future returned by [`LocalSet::run_until`]
+    pub struct RunUntil<'a, F> {
+        local_set: &'a LocalSet,
+        #[pin]
+        future: F,
+    }
+}
+
+/// Spawn a `!Send` future onto the `LocalSet` currently being driven
+///
+/// # Panics
+///
+/// Panics if called outside of a running [`LocalSet`].
+#[track_caller]
+pub fn spawn_local<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    spawn_local_task(Meta::new(None, Location::caller()), future)
+}
+
+/// Spawn the task described by `meta` onto the current `LocalSet`
+pub(crate) fn spawn_local_task<F>(meta: Arc<Meta>, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    CURRENT.with(|current| {
+        let current = current.borrow();
+        let context = current
+            .as_ref()
+            .expect("`spawn_local` called from outside of a `LocalSet`");
+        context.spawn(meta, future)
+    })
+}
+
+impl LocalSet {
+    /// Create an empty local set
+    pub fn new() -> Self {
+        Self {
+            context: Rc::new(LocalContext {
+                tasks: RefCell::new(HashMap::new()),
+                next_id: Cell::new(0),
+                shared: Arc::new(Shared {
+                    ready: Mutex::new(VecDeque::new()),
+                    waker: AtomicWaker::new(),
+                }),
+            }),
+        }
+    }
+
+    /// Spawn a `!Send` future onto this set
+    #[track_caller]
+    pub fn spawn_local<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        self.context.spawn(Meta::new(None, Location::caller()), future)
+    }
+
+    /// Drive `future` together with this set's tasks until `future` completes
+    pub fn run_until<F: Future>(&self, future: F) -> RunUntil<'_, F> {
+        RunUntil {
+            local_set: self,
+            future,
+        }
+    }
+
+    /// Run `future` and this set's tasks on the current thread using `runtime`
+    ///
+    /// Send tasks spawned from within keep running on the runtime's workers.
+    pub fn block_on<F: Future>(&self, runtime: &Runtime, future: F) -> F::Output {
+        runtime.block_on(self.run_until(future))
+    }
+
+    /// Number of tasks that have not completed yet
+    pub fn len(&self) -> usize {
+        self.context.tasks.borrow().len()
+    }
+
+    /// Whether every task in the set has completed
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Make this set current on the thread for the duration of `f`
+    fn with_current<R>(&self, f: impl FnOnce() -> R) -> R {
+        struct Reset(Option<Rc<LocalContext>>);
+
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                let prev = self.0.take();
+                CURRENT.with(|current| *current.borrow_mut() = prev);
+            }
+        }
+
+        let prev = CURRENT.with(|current| current.borrow_mut().replace(self.context.clone()));
+        let _reset = Reset(prev);
+        f()
+    }
+
+    /// Poll up to a tick's worth of ready tasks, returning `true` if more are ready
+    fn tick(&self) -> bool {
+        let context = &self.context;
+
+        for _ in 0..MAX_TASKS_PER_TICK {
+            let id = match context.shared.ready.lock().unwrap().pop_front() {
+                Some(id) => id,
+                None => return false,
+            };
+
+            // Take the task out so it can spawn new tasks while being polled
+            let task = context.tasks.borrow_mut().remove(&id);
+            let mut task = match task {
+                Some(task) => task,
+                None => continue,
+            };
+
+            task.waker.queued.store(false, Ordering::Release);
+
+            // Dropping an aborted task's future completes its handle as cancelled
+            if task.waker.cancelled.load(Ordering::Acquire) {
+                continue;
+            }
+
+            let waker = Waker::from(task.waker.clone());
+            let mut cx = Context::from_waker(&waker);
+
+            if crate::coop::budget(|| task.future.as_mut().poll(&mut cx)).is_pending() {
+                context.tasks.borrow_mut().insert(id, task);
+            }
+        }
+
+        !context.shared.ready.lock().unwrap().is_empty()
+    }
+}
+
+impl Default for LocalSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for LocalSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LocalSet").field("len", &self.len()).finish()
+    }
+}
+
+impl LocalContext {
+    fn spawn<F>(&self, meta: Arc<Meta>, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let control = TaskControl::new();
+        let completion = CompletionGuard::new(control.clone());
+
+        let id = self.next_id.get();
+        self.next_id.set(id.wrapping_add(1));
+
+        let waker = Arc::new(TaskWaker {
+            id,
+            queued: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+            shared: self.shared.clone(),
+        });
+
+        // Panics are reported through the handle instead of unwinding out of the set
+        let future = Box::pin(Tracked::new(meta.id, async move {
+            let result = AssertUnwindSafe(future).catch_unwind().await;
+            completion.complete(result.map_err(JoinError::panic));
+        }));
+        let handle = JoinHandle::with_abort(control, waker.clone(), meta);
+
+        self.tasks.borrow_mut().insert(id, LocalTask { future, waker: waker.clone() });
+        waker.wake();
+        handle
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            self.shared.ready.lock().unwrap().push_back(self.id);
+            self.shared.waker.wake();
+        }
+    }
+}
+
+impl RemoteAbort for TaskWaker {
+    fn abort(self: Arc<Self>) {
+        self.cancelled.store(true, Ordering::Release);
+        self.wake_by_ref();
+    }
+}
+
+impl Future for LocalSet {
+    type Output = ();
+
+    /// Resolves once every task in the set has completed
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.context.shared.waker.register(cx.waker());
+
+        if self.with_current(|| self.tick()) {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+
+        if self.is_empty() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<F: Future> Future for RunUntil<'_, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = self.project();
+        let local_set = *this.local_set;
+        local_set.context.shared.waker.register(cx.waker());
+
+        local_set.with_current(|| {
+            if let Poll::Ready(output) = this.future.poll(cx) {
+                return Poll::Ready(output);
+            }
+
+            if local_set.tick() {
+                cx.waker().wake_by_ref();
+            }
+
+            Poll::Pending
+        })
+    }
+}
@@ -0,0 +1,251 @@
+//! Reference-counted task cells and the waker vtable that drives them
+
+use super::{Priority, RemoteAbort};
+use crate::scheduler::Scheduler;
+use std::future::Future;
+use std::mem::ManuallyDrop;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, RawWaker, RawWakerVTable, Waker};
+
+/// Task is queued (or about to be queued) in the scheduler
+const SCHEDULED: usize = 0b0001;
+
+/// Task is currently being polled by a worker
+const RUNNING: usize = 0b0010;
+
+/// Task was woken while running and must be polled again
+const NOTIFIED: usize = 0b0100;
+
+/// Task future has completed and been dropped
+const COMPLETE: usize = 0b1000;
+
+/// Task was aborted; its future is dropped instead of polled on the next run
+const CANCELLED: usize = 0b1_0000;
+
+/// Type-erased future stored in a task cell
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Shared task cell, referenced by the run queues and by every waker
+pub(crate) struct Header {
+    /// Lifecycle bits (`SCHEDULED`, `RUNNING`, `NOTIFIED`, `COMPLETE`, `CANCELLED`)
+    state: AtomicUsize,
+
+    /// The future itself; only locked by the worker holding `RUNNING`
+    future: parking_lot::Mutex<Option<BoxFuture>>,
+
+    /// Scheduler the task is pushed back into when woken
+    scheduler: Arc<Scheduler>,
+
+    /// Which of the scheduler's queues the task is pushed into
+    priority: Priority,
+}
+
+/// Runnable reference to a spawned task
+///
+/// Exactly one `RawTask` exists per `SCHEDULED` transition, so a task is
+/// never sitting in two run queues at once.
+pub struct RawTask {
+    header: Arc<Header>,
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone_waker, wake, wake_by_ref, drop_waker);
+
+impl RawTask {
+    /// Allocate a new task cell in the `SCHEDULED` state
+    pub(crate) fn new<F>(future: F, scheduler: Arc<Scheduler>, priority: Priority) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let header = Arc::new(Header {
+            state: AtomicUsize::new(SCHEDULED),
+            future: parking_lot::Mutex::new(Some(Box::pin(future))),
+            scheduler,
+            priority,
+        });
+
+        Self { header }
+    }
+
+    /// Scheduling priority of the task
+    pub fn priority(&self) -> Priority {
+        self.header.priority
+    }
+
+    /// Handle used by `JoinHandle::abort` to cancel this task
+    pub(crate) fn remote_abort(&self) -> Arc<dyn RemoteAbort> {
+        self.header.clone()
+    }
+
+    /// Task cell, kept by the runtime so shutdown can cancel the task
+    pub(crate) fn header(&self) -> Arc<Header> {
+        self.header.clone()
+    }
+
+    /// Poll the task once, rescheduling it if it was woken mid-poll
+    pub fn run(self) {
+        let header = &self.header;
+
+        // SCHEDULED -> RUNNING
+        let mut state = header.state.load(Ordering::Acquire);
+        loop {
+            debug_assert!(state & SCHEDULED != 0, "running a task that was not scheduled");
+            let next = (state & !SCHEDULED) | RUNNING;
+            match header.state.compare_exchange_weak(state, next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => break,
+                Err(actual) => state = actual,
+            }
+        }
+
+        if state & CANCELLED != 0 {
+            // Dropping the future completes the join handle with a cancellation error
+            let future = header.future.lock().take();
+            drop(future);
+            header.state.store(COMPLETE, Ordering::Release);
+            return;
+        }
+
+        let waker = ManuallyDrop::new(unsafe { Waker::from_raw(raw_waker(Arc::as_ptr(header))) });
+        let mut cx = Context::from_waker(&waker);
+
+        let mut slot = header.future.lock();
+        let finished = match slot.as_mut() {
+            Some(future) => crate::coop::budget(|| future.as_mut().poll(&mut cx)).is_ready(),
+            None => true,
+        };
+
+        if finished {
+            *slot = None;
+            drop(slot);
+            header.state.store(COMPLETE, Ordering::Release);
+            return;
+        }
+        drop(slot);
+
+        // RUNNING -> idle, or straight back into the queue if woken meanwhile
+        let mut state = header.state.load(Ordering::Acquire);
+        loop {
+            if state & CANCELLED != 0 {
+                // Cancelled by a runtime shutdown while being polled
+                let future = header.future.lock().take();
+                drop(future);
+                header.state.store(COMPLETE, Ordering::Release);
+                return;
+            }
+
+            let next = if state & NOTIFIED != 0 {
+                (state & !(RUNNING | NOTIFIED)) | SCHEDULED
+            } else {
+                state & !RUNNING
+            };
+
+            match header.state.compare_exchange_weak(state, next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => {
+                    if next & SCHEDULED != 0 {
+                        self.header.schedule();
+                    }
+                    return;
+                }
+                Err(actual) => state = actual,
+            }
+        }
+    }
+}
+
+impl Header {
+    /// Transition towards `SCHEDULED`, returning `true` if the caller must enqueue the task
+    fn transition_to_scheduled(&self) -> bool {
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            if state & (COMPLETE | SCHEDULED | NOTIFIED) != 0 {
+                return false;
+            }
+
+            let next = if state & RUNNING != 0 {
+                state | NOTIFIED
+            } else {
+                state | SCHEDULED
+            };
+
+            match self.state.compare_exchange_weak(state, next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return next & SCHEDULED != 0,
+                Err(actual) => state = actual,
+            }
+        }
+    }
+
+    /// Mark the task cancelled, returning `false` if it already completed
+    fn transition_to_cancelled(&self) -> bool {
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            if state & (COMPLETE | CANCELLED) != 0 {
+                return false;
+            }
+
+            match self.state.compare_exchange_weak(state, state | CANCELLED, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return true,
+                Err(actual) => state = actual,
+            }
+        }
+    }
+
+    /// Cancel the task as part of a runtime shutdown
+    ///
+    /// The future is dropped right away unless a worker is polling it, in
+    /// which case that worker drops it once the poll returns.
+    pub(crate) fn shutdown(&self) {
+        if !self.transition_to_cancelled() {
+            return;
+        }
+
+        let future = match self.future.try_lock() {
+            Some(mut slot) => slot.take(),
+            None => return,
+        };
+        self.state.fetch_or(COMPLETE, Ordering::AcqRel);
+        drop(future);
+    }
+
+    /// Push the task into its scheduler's run queue
+    fn schedule(self: Arc<Self>) {
+        let scheduler = self.scheduler.clone();
+        scheduler.schedule(RawTask { header: self });
+    }
+}
+
+impl RemoteAbort for Header {
+    fn abort(self: Arc<Self>) {
+        // Schedule the task so a worker notices the flag and drops the future
+        if self.transition_to_cancelled() && self.transition_to_scheduled() {
+            self.schedule();
+        }
+    }
+}
+
+fn raw_waker(header: *const Header) -> RawWaker {
+    RawWaker::new(header as *const (), &VTABLE)
+}
+
+unsafe fn clone_waker(ptr: *const ()) -> RawWaker {
+    Arc::increment_strong_count(ptr as *const Header);
+    raw_waker(ptr as *const Header)
+}
+
+unsafe fn wake(ptr: *const ()) {
+    let header = Arc::from_raw(ptr as *const Header);
+    if header.transition_to_scheduled() {
+        header.schedule();
+    }
+}
+
+unsafe fn wake_by_ref(ptr: *const ()) {
+    let header = ManuallyDrop::new(Arc::from_raw(ptr as *const Header));
+    if header.transition_to_scheduled() {
+        Arc::clone(&header).schedule();
+    }
+}
+
+unsafe fn drop_waker(ptr: *const ()) {
+    drop(Arc::from_raw(ptr as *const Header));
+}
@@ -0,0 +1,231 @@
+//! Scoped tasks that may borrow from the spawning stack frame
+
+use super::{AbortHandle, CompletionGuard, JoinError, JoinHandle, Meta, Priority, TaskControl};
+use futures::FutureExt;
+use std::collections::HashMap;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::panic::{AssertUnwindSafe, Location};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+type BoxFuture<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+
+/// Run `f` and wait until every task spawned on its [`Scope`] has finished
+///
+/// Unlike [`spawn`](crate::spawn), tasks spawned on the scope may borrow
+/// anything that outlives the call to `scope`. The calling thread blocks
+/// until the future returned by `f` and all child tasks, including any they
+/// spawn through a clone of the scope, have completed; if
+/// `f` panics, the children are aborted first and the panic resumes once
+/// they have been dropped. A child's panic is reported through its
+/// [`JoinHandle`] rather than propagated.
+///
+/// Inside a runtime worker the worker's queue is handed off for the
+/// duration, as with [`block_in_place`](super::block_in_place).
+///
+/// ```no_run
+/// let words = vec!["a".to_string(), "bb".to_string(), "ccc".to_string()];
+///
+/// let total = cycle::scope(|s| {
+///     let words = &words;
+///     async move {
+///         let handles: Vec<_> = words.iter().map(|word| s.spawn(async move { word.len() })).collect();
+///
+///         let mut total = 0;
+///         for handle in handles {
+///             total += handle.await.unwrap();
+///         }
+///         total
+///     }
+/// });
+/// assert_eq!(total, 6);
+/// ```
+pub fn scope<'env, F, Fut, R>(f: F) -> R
+where
+    F: FnOnce(Scope<'env>) -> Fut,
+    Fut: Future<Output = R>,
+{
+    let state = Arc::new(ScopeState {
+        inner: Mutex::new(Inner {
+            closed: false,
+            next_id: 0,
+            children: HashMap::new(),
+            waiter: None,
+        }),
+    });
+
+    let scope = Scope {
+        state: state.clone(),
+        _env: PhantomData,
+    };
+
+    // Waits for the children on the way out, including when unwinding
+    let _wait = WaitForChildren(&state);
+
+    crate::runtime::handle::with_current(|handle| {
+        super::block_in_place(|| handle.block_on(f(scope)))
+    })
+}
+
+/// Spawns tasks that may borrow data living for `'env`
+///
+/// Cheap to clone; clones spawn into the same scope.
+#[derive(Clone)]
+pub struct Scope<'env> {
+    state: Arc<ScopeState>,
+
+    /// Invariant over `'env`, like `std::thread::Scope`
+    _env: PhantomData<&'env mut &'env ()>,
+}
+
+struct ScopeState {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    /// Set once every child is gone and `scope` is about to return
+    closed: bool,
+
+    /// Id for the next child
+    next_id: usize,
+
+    /// Children whose futures have not been dropped yet, with their abort
+    /// handles once `spawn` has them
+    children: HashMap<usize, Option<AbortHandle>>,
+
+    /// Woken whenever a child is dropped
+    waiter: Option<Waker>,
+}
+
+/// Child task wrapper that deregisters from the scope once its future is dropped
+struct Child {
+    id: usize,
+    future: Option<BoxFuture<'static>>,
+    state: Arc<ScopeState>,
+}
+
+struct WaitForChildren<'a>(&'a ScopeState);
+
+impl<'env> Scope<'env> {
+    /// Spawn a task that may borrow data living for `'env`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the scope has already returned, e.g. when called through a
+    /// clone of the scope that outlived it.
+    #[track_caller]
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'env,
+        F::Output: Send + 'env,
+    {
+        let meta = Meta::new(None, Location::caller());
+        let control = TaskControl::new();
+        let completion = CompletionGuard::new(control.clone());
+
+        let future: BoxFuture<'env> = Box::pin(async move {
+            let result = AssertUnwindSafe(future).catch_unwind().await;
+            completion.complete(result.map_err(JoinError::panic));
+        });
+
+        // SAFETY: `scope` does not return (or unwind) until every child future
+        // has been dropped, and no child can be spawned once it has seen
+        // them all gone, so nothing borrowed for `'env` is used after `'env` ends.
+        let future: BoxFuture<'static> = unsafe { std::mem::transmute(future) };
+
+        let mut inner = self.state.inner.lock().unwrap();
+        assert!(!inner.closed, "`Scope::spawn` called after the scope ended");
+
+        let id = inner.next_id;
+        inner.next_id += 1;
+
+        // Registered before spawning, so the child can't deregister first
+        inner.children.insert(id, None);
+        drop(inner);
+
+        // Not under the lock: a closed runtime drops the child right away
+        let child = Child {
+            id,
+            future: Some(future),
+            state: self.state.clone(),
+        };
+        let handle = crate::runtime::handle::with_current(|handle| {
+            handle.spawn_task(meta.clone(), Priority::Normal, child)
+        });
+
+        let mut inner = self.state.inner.lock().unwrap();
+        if let Some(abort) = inner.children.get_mut(&id) {
+            *abort = Some(handle.abort_handle());
+        }
+        drop(inner);
+
+        JoinHandle::with_abort(control, Arc::new(handle.abort_handle()), meta)
+    }
+}
+
+impl std::fmt::Debug for Scope<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let inner = self.state.inner.lock().unwrap();
+        f.debug_struct("Scope")
+            .field("running", &inner.children.len())
+            .finish()
+    }
+}
+
+impl Future for Child {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        match self.future.as_mut() {
+            Some(future) => future.as_mut().poll(cx),
+            None => Poll::Ready(()),
+        }
+    }
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        // Drop the borrowing future before telling the scope it may return
+        drop(self.future.take());
+
+        let mut inner = self.state.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.children.remove(&self.id);
+        let waiter = inner.waiter.take();
+        drop(inner);
+
+        if let Some(waiter) = waiter {
+            waiter.wake();
+        }
+    }
+}
+
+impl Drop for WaitForChildren<'_> {
+    fn drop(&mut self) {
+        let panicking = thread::panicking();
+
+        // Wait through `block_on` so a current-thread runtime keeps running
+        // the children nobody awaited. Children may still spawn more until
+        // the last one is gone, at which point the scope closes.
+        let children_done = std::future::poll_fn(|cx| {
+            let mut inner = self.0.inner.lock().unwrap_or_else(|e| e.into_inner());
+            if inner.children.is_empty() {
+                inner.closed = true;
+                return Poll::Ready(());
+            }
+
+            if panicking {
+                for abort in inner.children.values().flatten() {
+                    abort.abort();
+                }
+            }
+            inner.waiter = Some(cx.waker().clone());
+            Poll::Pending
+        });
+        crate::runtime::handle::with_current(|handle| {
+            super::block_in_place(|| handle.block_on(children_done))
+        });
+    }
+}
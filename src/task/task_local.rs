@@ -0,0 +1,192 @@
+//! Values scoped to a future rather than to an OS thread
+
+use std::cell::RefCell;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::thread;
+
+/// Declare task-local keys of type [`LocalKey`]
+///
+/// Values are set for the duration of a future with [`LocalKey::scope`] and
+/// are visible to any code polled inside it, no matter which worker thread
+/// the task happens to be running on. Spawned tasks don't inherit them.
+///
+/// ```no_run
+/// cycle::task_local! {
+///     static REQUEST_ID: u64;
+/// }
+///
+/// cycle::block_on(REQUEST_ID.scope(42, async {
+///     assert_eq!(REQUEST_ID.get(), 42);
+/// }));
+/// ```
+#[macro_export]
+macro_rules! task_local {
+    () => {};
+
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty; $($rest:tt)*) => {
+        $crate::__task_local_inner!($(#[$attr])* $vis $name, $t);
+        $crate::task_local!($($rest)*);
+    };
+
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty) => {
+        $crate::__task_local_inner!($(#[$attr])* $vis $name, $t);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __task_local_inner {
+    ($(#[$attr:meta])* $vis:vis $name:ident, $t:ty) => {
+        $(#[$attr])*
+        $vis static $name: $crate::task::LocalKey<$t> = {
+            ::std::thread_local! {
+                static __KEY: ::std::cell::RefCell<::std::option::Option<$t>> =
+                    const { ::std::cell::RefCell::new(::std::option::Option::None) };
+            }
+
+            $crate::task::LocalKey { inner: __KEY }
+        };
+    };
+}
+
+/// Key for task-local data, declared with [`task_local!`](crate::task_local)
+pub struct LocalKey<T: 'static> {
+    /// Slot holding the value of the scope being polled on this thread
+    #[doc(hidden)]
+    pub inner: thread::LocalKey<RefCell<Option<T>>>,
+}
+
+pin_project_lite::pin_project! {
+    /// Future returned by [`LocalKey::scope`]
+    #[must_use = "futures do nothing unless polled"]
+    pub struct TaskLocalFuture<T: 'static, F> {
+        local: &'static LocalKey<T>,
+
+        // Holds the value whenever the future isn't being polled
+        slot: Option<T>,
+
+        #[pin]
+        future: Option<F>,
+    }
+
+    impl<T: 'static, F> PinnedDrop for TaskLocalFuture<T, F> {
+        fn drop(this: Pin<&mut Self>) {
+            let this = this.project();
+            let mut future = this.future;
+
+            // Drop the future inside the scope so its destructors see the value
+            if future.is_some() {
+                this.local.enter(this.slot, || future.set(None));
+            }
+        }
+    }
+}
+
+/// Error returned by [`LocalKey::try_with`] outside of a scope
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessError(());
+
+impl<T: 'static> LocalKey<T> {
+    /// Set the value to `value` while `future` is polled
+    ///
+    /// Scopes of the same key nest; the innermost value is visible.
+    pub fn scope<F: Future>(&'static self, value: T, future: F) -> TaskLocalFuture<T, F> {
+        TaskLocalFuture {
+            local: self,
+            slot: Some(value),
+            future: Some(future),
+        }
+    }
+
+    /// Set the value to `value` while the closure `f` runs
+    pub fn sync_scope<R>(&'static self, value: T, f: impl FnOnce() -> R) -> R {
+        let mut slot = Some(value);
+        self.enter(&mut slot, f)
+    }
+
+    /// Run `f` with a reference to the current value
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a [`LocalKey::scope`] for this key.
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        match self.try_with(f) {
+            Ok(result) => result,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Run `f` with a reference to the current value, if it is set
+    pub fn try_with<R>(&'static self, f: impl FnOnce(&T) -> R) -> Result<R, AccessError> {
+        self.inner.with(|cell| match cell.borrow().as_ref() {
+            Some(value) => Ok(f(value)),
+            None => Err(AccessError(())),
+        })
+    }
+
+    /// Swap the value in `slot` into the thread-local while `f` runs
+    fn enter<R>(&'static self, slot: &mut Option<T>, f: impl FnOnce() -> R) -> R {
+        struct Reset<'a, T: 'static> {
+            local: &'static LocalKey<T>,
+            slot: &'a mut Option<T>,
+        }
+
+        impl<T: 'static> Drop for Reset<'_, T> {
+            fn drop(&mut self) {
+                self.local.inner.with(|cell| std::mem::swap(self.slot, &mut *cell.borrow_mut()));
+            }
+        }
+
+        self.inner.with(|cell| std::mem::swap(slot, &mut *cell.borrow_mut()));
+        let _reset = Reset { local: self, slot };
+        f()
+    }
+}
+
+impl<T: Clone + 'static> LocalKey<T> {
+    /// Copy of the current value
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a [`LocalKey::scope`] for this key.
+    pub fn get(&'static self) -> T {
+        self.with(T::clone)
+    }
+}
+
+impl<T: 'static> fmt::Debug for LocalKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalKey").finish_non_exhaustive()
+    }
+}
+
+impl<T: 'static, F: Future> Future for TaskLocalFuture<T, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = self.project();
+        let mut future = this.future;
+
+        this.local.enter(this.slot, || match future.as_mut().as_pin_mut() {
+            Some(future) => future.poll(cx),
+            None => unreachable!("the future is only taken when dropped"),
+        })
+    }
+}
+
+impl<T: 'static + fmt::Debug, F> fmt::Debug for TaskLocalFuture<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskLocalFuture").field("value", &self.slot).finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "task-local value not set; access it from inside `LocalKey::scope`")
+    }
+}
+
+impl std::error::Error for AccessError {}
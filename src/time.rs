@@ -1,35 +1,29 @@
 //! High-performance timer system with timer wheels
 
-use std::collections::BinaryHeap;
+use std::collections::{BTreeMap, BinaryHeap};
 use std::cmp::Reverse;
 use std::future::Future;
 use std::pin::Pin;
-use std::sync::{Arc, Mutex};
-use std::task::{Context, Poll, Waker};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::task::{ready, Context, Poll, Waker};
 use std::time::{Duration, Instant};
-use std::thread;
-use once_cell::sync::Lazy;
+use crate::runtime::Handle;
 
 /// Re-export standard time types
 pub use std::time::{SystemTime, UNIX_EPOCH};
 
-/// Global timer wheel
-static TIMER_WHEEL: Lazy<Arc<TimerWheel>> = Lazy::new(|| {
-    let wheel = Arc::new(TimerWheel::new());
-    let wheel_clone = wheel.clone();
-    
-    // Start timer thread
-    thread::spawn(move || {
-        wheel_clone.run();
-    });
-    
-    wheel
-});
-
 /// Timer wheel for efficient timer management
-struct TimerWheel {
+///
+/// Every runtime with time enabled owns one. Its timers fire whenever the
+/// runtime's driver is turned, and an idle worker blocks on the driver no
+/// longer than until the earliest of them is due.
+pub(crate) struct TimerWheel {
     timers: Mutex<BinaryHeap<Reverse<Timer>>>,
     next_id: std::sync::atomic::AtomicU64,
+    
+    /// Set once the runtime shuts down
+    shutdown: AtomicBool,
 }
 
 /// Individual timer
@@ -43,14 +37,30 @@ struct Timer {
 
 
 impl TimerWheel {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             timers: Mutex::new(BinaryHeap::new()),
             next_id: std::sync::atomic::AtomicU64::new(1),
+            shutdown: AtomicBool::new(false),
         }
     }
     
-    fn add_timer(&self, deadline: Instant, waker: Waker) -> u64 {
+    /// Drop every pending timer; they never fire
+    pub(crate) fn shutdown(&self) {
+        let mut timers = self.timers.lock().unwrap();
+        self.shutdown.store(true, Ordering::Release);
+        
+        // Drop the wakers, which may hold on to tasks of the runtime
+        timers.clear();
+    }
+    
+    /// Add a timer, returning whether it is now the earliest one, in which
+    /// case the driver has to be woken to wait for it
+    ///
+    /// # Panics
+    ///
+    /// Panics if the runtime owning the wheel has shut down.
+    fn add_timer(&self, deadline: Instant, waker: Waker) -> bool {
         let id = self.next_id.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
         let timer = Timer {
             id,
@@ -58,39 +68,310 @@ impl TimerWheel {
             waker: Some(waker),
         };
         
-        self.timers.lock().unwrap().push(Reverse(timer));
-        id
+        let mut timers = self.timers.lock().unwrap();
+        assert!(
+            !self.shutdown.load(Ordering::Acquire),
+            "the runtime this timer belongs to has shut down"
+        );
+        let earliest = timers.peek().is_none_or(|Reverse(next)| deadline < next.deadline);
+        timers.push(Reverse(timer));
+        earliest
+    }
+    
+    /// Deadline of the earliest pending timer
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.timers.lock().unwrap().peek().map(|Reverse(timer)| timer.deadline)
     }
     
-    fn run(&self) {
-        loop {
-            let now = Instant::now();
-            let mut expired_timers = Vec::new();
-            
-            // Collect expired timers
-            {
-                let mut timers = self.timers.lock().unwrap();
-                while let Some(Reverse(timer)) = timers.peek() {
-                    if timer.deadline <= now {
-                        if let Some(Reverse(timer)) = timers.pop() {
-                            expired_timers.push(timer);
-                        }
-                    } else {
-                        break;
-                    }
+    /// Wake every timer whose deadline has passed
+    pub(crate) fn fire_expired(&self) {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        {
+            let mut timers = self.timers.lock().unwrap();
+            while timers.peek().is_some_and(|Reverse(timer)| timer.deadline <= now) {
+                if let Some(Reverse(timer)) = timers.pop() {
+                    expired.push(timer);
                 }
             }
-            
-            // Wake expired timers
-            for timer in expired_timers {
-                if let Some(waker) = timer.waker {
-                    waker.wake();
+        }
+        wake_all(expired.into_iter().filter_map(|timer| timer.waker));
+    }
+}
+
+/// Paused clock of a runtime, along with the timers waiting on it
+///
+/// While a runtime's clock is paused, time only moves through [`advance`] or
+/// by jumping to the next timer once the runtime has nothing else to do.
+pub(crate) struct Clock {
+    /// Fast path for the common case of a clock that was never paused
+    paused: AtomicBool,
+    inner: Mutex<PausedClock>,
+}
+
+#[derive(Default)]
+struct PausedClock {
+    /// Frozen time, `None` while the clock follows real time
+    now: Option<Instant>,
+    
+    /// Wakers of pending sleeps, by deadline and id
+    timers: BTreeMap<(Instant, u64), Waker>,
+    next_id: u64,
+}
+
+/// Where a sleep's timer is registered, so it can be updated and removed
+#[derive(Debug, Clone, Copy)]
+enum Registration {
+    /// With the paused clock, under this id
+    Paused(u64),
+    
+    /// With the runtime's timer wheel
+    Wheel,
+}
+
+/// A deadline on a runtime's clock, registered the first time it is polled
+/// and removed from a paused clock when dropped
+struct TimerEntry {
+    handle: Handle,
+    deadline: Instant,
+    registration: Option<Registration>,
+}
+
+/// Current time, as seen by the current runtime's clock
+///
+/// This is [`Instant::now`] unless the runtime's clock has been paused.
+pub fn now() -> Instant {
+    with_clock(|clock| clock.and_then(Clock::paused_now)).unwrap_or_else(Instant::now)
+}
+
+/// Pause the current runtime's clock
+///
+/// Sleeps and timeouts then only complete once [`advance`] moves time past
+/// their deadline, or once the runtime runs out of other work, at which point
+/// the clock jumps straight to the next deadline. This makes tests of
+/// time-dependent code fast and deterministic.
+///
+/// # Panics
+///
+/// Panics if called outside of a current-thread runtime or if the clock is
+/// already paused.
+pub fn pause() {
+    with_paused_clock("pause", |clock| clock.pause());
+}
+
+/// Resume the current runtime's clock after [`pause`]
+///
+/// The clock returns to real time; sleeps still pending keep their deadlines
+/// and complete once real time reaches them.
+///
+/// # Panics
+///
+/// Panics if called outside of a runtime whose clock is paused.
+pub fn resume() {
+    with_paused_clock("resume", |clock| clock.resume());
+}
+
+/// Move the current runtime's paused clock forward by `duration`
+///
+/// Timers whose deadline has been reached are fired, and the calling task
+/// yields once so the tasks they woke get to run.
+///
+/// # Panics
+///
+/// Panics if called outside of a runtime whose clock is paused.
+pub async fn advance(duration: Duration) {
+    with_paused_clock("advance", |clock| clock.advance(duration));
+    yield_now().await
+}
+
+/// Run `f` with the current runtime's clock
+fn with_clock<R>(f: impl FnOnce(Option<&Clock>) -> R) -> R {
+    let handle = crate::runtime::Handle::try_current().ok();
+    f(handle.as_ref().map(|handle| handle.clock()))
+}
+
+/// Run `f` with the clock of the current-thread runtime `function` was called on
+fn with_paused_clock<R>(function: &str, f: impl FnOnce(&Clock) -> R) -> R {
+    let handle = match crate::runtime::Handle::try_current() {
+        Ok(handle) if handle.flavor() == crate::runtime::RuntimeFlavor::CurrentThread => handle,
+        _ => panic!("`time::{}` requires a current-thread runtime", function),
+    };
+    f(handle.clock())
+}
+
+/// Runtime a new timer is bound to: the current one, or the global runtime outside of one
+fn timer_handle() -> Handle {
+    crate::runtime::handle::with_current(Handle::clone)
+}
+
+impl TimerEntry {
+    /// Deadline on the current runtime, or on the global runtime outside of one
+    fn new(deadline: Instant) -> Self {
+        Self {
+            handle: timer_handle(),
+            deadline,
+            registration: None,
+        }
+    }
+    
+    /// Check whether the deadline has passed, registering `cx` to be woken when it does
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let handle = &self.handle;
+        if let Some(poll) = handle.clock().poll_paused(self.deadline, &mut self.registration, cx.waker()) {
+            return poll;
+        }
+        
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        
+        // A registration with a since-resumed paused clock is gone
+        if !matches!(self.registration, Some(Registration::Wheel)) {
+            if handle.timer().add_timer(self.deadline, cx.waker().clone()) {
+                handle.unpark_driver();
+            }
+            self.registration = Some(Registration::Wheel);
+        }
+        
+        Poll::Pending
+    }
+}
+
+impl Drop for TimerEntry {
+    fn drop(&mut self) {
+        if let Some(Registration::Paused(id)) = self.registration {
+            self.handle.clock().cancel_paused(self.deadline, id);
+        }
+    }
+}
+
+impl Clock {
+    pub(crate) fn new(start_paused: bool) -> Self {
+        let clock = Self {
+            paused: AtomicBool::new(false),
+            inner: Mutex::new(PausedClock::default()),
+        };
+        if start_paused {
+            clock.pause();
+        }
+        clock
+    }
+    
+    /// Whether the clock is paused
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Acquire)
+    }
+    
+    fn paused_now(&self) -> Option<Instant> {
+        if !self.is_paused() {
+            return None;
+        }
+        self.inner.lock().unwrap().now
+    }
+    
+    fn pause(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        assert!(inner.now.is_none(), "time is already paused");
+        inner.now = Some(Instant::now());
+        self.paused.store(true, Ordering::Release);
+    }
+    
+    fn resume(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        assert!(inner.now.take().is_some(), "time is not paused");
+        self.paused.store(false, Ordering::Release);
+        
+        // Woken sleeps re-register with the real timer driver
+        let timers = std::mem::take(&mut inner.timers);
+        drop(inner);
+        wake_all(timers.into_values());
+    }
+    
+    fn advance(&self, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        let now = inner.now.as_mut().expect("time is not paused");
+        *now += duration;
+        let expired = inner.take_expired();
+        drop(inner);
+        wake_all(expired);
+    }
+    
+    /// Jump to the next timer's deadline and fire it, returning `false` if
+    /// the clock isn't paused or no timers are pending
+    pub(crate) fn auto_advance(&self) -> bool {
+        if !self.is_paused() {
+            return false;
+        }
+        
+        let mut inner = self.inner.lock().unwrap();
+        let next = match (inner.now, inner.timers.keys().next()) {
+            (Some(now), Some(&(deadline, _))) => now.max(deadline),
+            _ => return false,
+        };
+        inner.now = Some(next);
+        let expired = inner.take_expired();
+        drop(inner);
+        wake_all(expired);
+        true
+    }
+    
+    /// Poll `deadline` against the paused clock, or `None` if it isn't paused
+    ///
+    /// The first poll registers a timer; later polls update its waker in place.
+    fn poll_paused(&self, deadline: Instant, registration: &mut Option<Registration>, waker: &Waker) -> Option<Poll<()>> {
+        if !self.is_paused() {
+            return None;
+        }
+        
+        let mut inner = self.inner.lock().unwrap();
+        let now = inner.now?;
+        if now >= deadline {
+            return Some(Poll::Ready(()));
+        }
+        
+        if let Some(Registration::Paused(id)) = *registration {
+            if let Some(current) = inner.timers.get_mut(&(deadline, id)) {
+                if !current.will_wake(waker) {
+                    *current = waker.clone();
                 }
+                return Some(Poll::Pending);
             }
-            
-            // Sleep for a short time
-            thread::sleep(Duration::from_millis(1));
         }
+        
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.timers.insert((deadline, id), waker.clone());
+        *registration = Some(Registration::Paused(id));
+        Some(Poll::Pending)
+    }
+    
+    /// Forget a paused-clock timer whose sleep was dropped
+    fn cancel_paused(&self, deadline: Instant, id: u64) {
+        self.inner.lock().unwrap().timers.remove(&(deadline, id));
+    }
+}
+
+impl PausedClock {
+    fn take_expired(&mut self) -> Vec<Waker> {
+        let now = match self.now {
+            Some(now) => now,
+            None => return Vec::new(),
+        };
+        
+        let mut expired = Vec::new();
+        while let Some(entry) = self.timers.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            expired.push(entry.remove());
+        }
+        expired
+    }
+}
+
+fn wake_all(wakers: impl IntoIterator<Item = Waker>) {
+    for waker in wakers {
+        waker.wake();
     }
 }
 
@@ -114,15 +395,13 @@ where
 
 /// Sleep future implementation
 struct SleepFuture {
-    deadline: Instant,
-    timer_id: Option<u64>,
+    entry: TimerEntry,
 }
 
 impl SleepFuture {
     fn new(duration: Duration) -> Self {
         Self {
-            deadline: Instant::now() + duration,
-            timer_id: None,
+            entry: TimerEntry::new(now() + duration),
         }
     }
 }
@@ -131,32 +410,21 @@ impl Future for SleepFuture {
     type Output = ();
     
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
-        let now = Instant::now();
-        
-        if now >= self.deadline {
-            return Poll::Ready(());
-        }
+        ready!(crate::coop::poll_proceed(cx));
         
-        if self.timer_id.is_none() {
-            let timer_id = TIMER_WHEEL.add_timer(self.deadline, cx.waker().clone());
-            self.timer_id = Some(timer_id);
-        }
-        
-        Poll::Pending
+        self.entry.poll(cx)
     }
 }
 
 /// Sleep until future implementation
 struct SleepUntilFuture {
-    deadline: Instant,
-    timer_id: Option<u64>,
+    entry: TimerEntry,
 }
 
 impl SleepUntilFuture {
     fn new(deadline: Instant) -> Self {
         Self {
-            deadline,
-            timer_id: None,
+            entry: TimerEntry::new(deadline),
         }
     }
 }
@@ -165,18 +433,9 @@ impl Future for SleepUntilFuture {
     type Output = ();
     
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
-        let now = Instant::now();
-        
-        if now >= self.deadline {
-            return Poll::Ready(());
-        }
-        
-        if self.timer_id.is_none() {
-            let timer_id = TIMER_WHEEL.add_timer(self.deadline, cx.waker().clone());
-            self.timer_id = Some(timer_id);
-        }
+        ready!(crate::coop::poll_proceed(cx));
         
-        Poll::Pending
+        self.entry.poll(cx)
     }
 }
 
@@ -185,8 +444,7 @@ pin_project_lite::pin_project! {
     struct TimeoutFuture<F> {
         #[pin]
         future: F,
-        deadline: Instant,
-        timer_id: Option<u64>,
+        entry: TimerEntry,
     }
 }
 
@@ -194,8 +452,7 @@ impl<F: Future> TimeoutFuture<F> {
     fn new(duration: Duration, future: F) -> Self {
         Self {
             future,
-            deadline: Instant::now() + duration,
-            timer_id: None,
+            entry: TimerEntry::new(now() + duration),
         }
     }
 }
@@ -211,19 +468,8 @@ impl<F: Future> Future for TimeoutFuture<F> {
             return Poll::Ready(Ok(output));
         }
         
-        // Check timeout
-        let now = Instant::now();
-        if now >= *this.deadline {
-            return Poll::Ready(Err(TimeoutError));
-        }
-        
-        // Register timer if not already done
-        if this.timer_id.is_none() {
-            let timer_id = TIMER_WHEEL.add_timer(*this.deadline, cx.waker().clone());
-            *this.timer_id = Some(timer_id);
-        }
-        
-        Poll::Pending
+        // Check timeout, registering the timer if it hasn't fired yet
+        this.entry.poll(cx).map(|()| Err(TimeoutError))
     }
 }
 
@@ -250,7 +496,7 @@ impl Interval {
     pub fn new(period: Duration) -> Self {
         Self {
             period,
-            next_tick: Instant::now() + period,
+            next_tick: now() + period,
         }
     }
     
@@ -282,11 +528,13 @@ pub async fn yield_now() {
     impl Future for YieldFuture {
         type Output = ();
         
-        fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
             if self.yielded {
                 Poll::Ready(())
             } else {
                 self.yielded = true;
+                // Reschedule ourselves behind whatever else is queued
+                cx.waker().wake_by_ref();
                 Poll::Pending
             }
         }
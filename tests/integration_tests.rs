@@ -0,0 +1,1086 @@
+//! Integration tests for CYCLE
+
+#[cfg(test)]
+mod runtime_tests {
+    use cycle::prelude::*;
+    use futures::channel::oneshot;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_idle_tasks_do_not_pin_workers() {
+        let runtime = Runtime::with_workers(2).unwrap();
+        let (done_tx, done_rx) = mpsc::channel();
+        let mut senders = Vec::new();
+
+        // Far more pending tasks than workers
+        for i in 0..500 {
+            let (tx, rx) = oneshot::channel::<usize>();
+            let done_tx = done_tx.clone();
+            senders.push(tx);
+            runtime.spawn(async move {
+                let value = rx.await.unwrap();
+                done_tx.send(value + i).unwrap();
+            });
+        }
+
+        runtime.spawn(async move {
+            for tx in senders {
+                tx.send(1).unwrap();
+            }
+        });
+
+        let mut total = 0;
+        for _ in 0..500 {
+            total += done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        assert_eq!(total, (0..500).map(|i| i + 1).sum::<usize>());
+    }
+
+    #[test]
+    fn test_yielding_tasks_complete() {
+        let runtime = Runtime::with_workers(1).unwrap();
+        let result = runtime.block_on(async {
+            let mut count = 0;
+            for _ in 0..100 {
+                cycle::time::yield_now().await;
+                count += 1;
+            }
+            count
+        });
+        assert_eq!(result, 100);
+    }
+
+    #[test]
+    fn test_builder_configures_workers() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let started = Arc::new(AtomicUsize::new(0));
+        let started_clone = started.clone();
+
+        let runtime = cycle::runtime::Builder::new()
+            .worker_threads(3)
+            .thread_name("test-pool")
+            .thread_stack_size(256 * 1024)
+            .on_thread_start(move || {
+                started_clone.fetch_add(1, Ordering::SeqCst);
+            })
+            .build()
+            .unwrap();
+
+        let name = runtime.block_on(runtime.spawn(async {
+            std::thread::current().name().unwrap().to_string()
+        }));
+        assert!(name.unwrap().starts_with("test-pool-"));
+
+        // Joins every worker, so all start hooks have run
+        runtime.shutdown();
+        assert_eq!(started.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_current_thread_runs_on_caller() {
+        use cycle::runtime::{Builder, RuntimeFlavor};
+
+        let runtime = Builder::new_current_thread().build().unwrap();
+        assert_eq!(runtime.flavor(), RuntimeFlavor::CurrentThread);
+
+        let caller = std::thread::current().id();
+        let (tx, rx) = mpsc::channel();
+        let handles: Vec<_> = (0..10)
+            .map(|_| {
+                let tx = tx.clone();
+                runtime.spawn(async move {
+                    tx.send(std::thread::current().id()).unwrap();
+                })
+            })
+            .collect();
+
+        let on_caller = runtime.block_on(async {
+            for handle in handles {
+                handle.await.unwrap();
+            }
+            std::thread::current().id()
+        });
+        assert_eq!(on_caller, caller);
+        for _ in 0..10 {
+            assert_eq!(rx.try_recv().unwrap(), caller);
+        }
+    }
+
+    #[test]
+    fn test_local_set_runs_non_send_tasks() {
+        use cycle::task::{self, LocalSet};
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let runtime = Runtime::with_workers(1).unwrap();
+        let local = LocalSet::new();
+        let counter = Rc::new(RefCell::new(0));
+
+        let total = local.block_on(&runtime, {
+            let counter = counter.clone();
+            async move {
+                for _ in 0..10 {
+                    let counter = counter.clone();
+                    task::spawn_local(async move {
+                        cycle::time::yield_now().await;
+                        *counter.borrow_mut() += 1;
+                    });
+                }
+
+                while *counter.borrow() < 10 {
+                    cycle::time::yield_now().await;
+                }
+                *counter.borrow()
+            }
+        });
+
+        assert_eq!(total, 10);
+        assert!(local.is_empty());
+    }
+
+    #[test]
+    fn test_spawn_blocking_returns_result() {
+        let runtime = cycle::runtime::Builder::new()
+            .worker_threads(1)
+            .max_blocking_threads(2)
+            .build()
+            .unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| runtime.spawn_blocking(move || {
+                std::thread::sleep(Duration::from_millis(5));
+                i * 2
+            }))
+            .collect();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut results = Vec::new();
+        for handle in handles {
+            while !handle.is_finished() {
+                assert!(Instant::now() < deadline, "blocking task never finished");
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            results.push(handle.try_result().unwrap().unwrap());
+        }
+        assert_eq!(results, (0..8).map(|i| i * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_block_in_place_hands_off_worker() {
+        let runtime = Runtime::with_workers(1).unwrap();
+        let (tx, rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel();
+
+        // Blocks the only worker until the second task has run
+        runtime.spawn(async move {
+            let value = cycle::task::block_in_place(|| rx.recv_timeout(Duration::from_secs(5)));
+            done_tx.send(value.is_ok()).unwrap();
+        });
+        runtime.spawn(async move {
+            tx.send(()).unwrap();
+        });
+
+        assert!(done_rx.recv_timeout(Duration::from_secs(10)).unwrap());
+    }
+
+    #[test]
+    fn test_idle_workers_park_and_wake() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let parks = Arc::new(AtomicUsize::new(0));
+        let parks_clone = parks.clone();
+
+        let runtime = cycle::runtime::Builder::new()
+            .worker_threads(2)
+            .spin_before_park(0)
+            .on_thread_park(move || {
+                parks_clone.fetch_add(1, Ordering::SeqCst);
+            })
+            .build()
+            .unwrap();
+
+        std::thread::sleep(Duration::from_millis(50));
+        let parked = parks.load(Ordering::SeqCst);
+        assert!(parked >= 2);
+
+        // Parked workers stay parked while idle...
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(parks.load(Ordering::SeqCst), parked);
+
+        // ...and are woken by new work
+        let (tx, rx) = mpsc::channel();
+        runtime.spawn(async move {
+            tx.send(()).unwrap();
+        });
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn test_idle_workers_steal_local_tasks() {
+        use std::collections::HashSet;
+        use std::sync::Arc;
+
+        let runtime = Arc::new(Runtime::with_workers(4).unwrap());
+        let (tx, rx) = mpsc::channel();
+
+        // Children land on the spawning worker's local queue
+        let spawner = runtime.clone();
+        runtime.spawn(async move {
+            for _ in 0..4 {
+                let tx = tx.clone();
+                spawner.spawn(async move {
+                    std::thread::sleep(Duration::from_millis(50));
+                    tx.send(std::thread::current().id()).unwrap();
+                });
+            }
+        });
+
+        let threads: HashSet<_> = (0..4)
+            .map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        assert!(threads.len() > 1);
+    }
+
+    #[test]
+    fn test_join_handle_wakes_awaiting_task() {
+        let runtime = Runtime::with_workers(2).unwrap();
+
+        let result = runtime.block_on(async {
+            // Completes on another thread well after the first poll
+            let handle = cycle::task::spawn_blocking(|| {
+                std::thread::sleep(Duration::from_millis(20));
+                21
+            });
+            handle.await.unwrap() * 2
+        });
+
+        assert_eq!(result, 42);
+    }
+
+    #[test]
+    fn test_abort_cancels_pending_task() {
+        let runtime = Runtime::with_workers(2).unwrap();
+        let (tx, rx) = oneshot::channel::<()>();
+        let (dropped_tx, dropped_rx) = mpsc::channel();
+
+        struct NotifyOnDrop(mpsc::Sender<()>);
+
+        impl Drop for NotifyOnDrop {
+            fn drop(&mut self) {
+                let _ = self.0.send(());
+            }
+        }
+
+        let guard = NotifyOnDrop(dropped_tx);
+        let handle = runtime.spawn(async move {
+            let _guard = guard;
+            // Never resolves on its own
+            let _ = rx.await;
+        });
+
+        let abort = handle.abort_handle();
+        abort.clone().abort();
+
+        let err = runtime.block_on(handle).unwrap_err();
+        assert!(err.is_cancelled());
+        dropped_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        drop(tx);
+    }
+
+    #[test]
+    fn test_panicking_task_reports_payload() {
+        let runtime = Runtime::with_workers(1).unwrap();
+
+        let handle = runtime.spawn(async {
+            panic!("boom");
+        });
+        let err = runtime.block_on(handle).unwrap_err();
+        assert!(err.is_panic());
+        assert_eq!(*err.into_panic().downcast::<&str>().unwrap(), "boom");
+
+        // The only worker survived the panic
+        assert_eq!(runtime.block_on(async { 1 + 1 }), 2);
+    }
+
+    #[test]
+    fn test_join_set_joins_in_completion_order() {
+        use cycle::task::JoinSet;
+
+        let runtime = Runtime::with_workers(2).unwrap();
+        let mut set = JoinSet::new();
+        for delay in [30u64, 10, 20] {
+            set.spawn_on(async move {
+                cycle::task::spawn_blocking(move || std::thread::sleep(Duration::from_millis(delay)))
+                    .await
+                    .unwrap();
+                delay
+            }, &runtime);
+        }
+        assert_eq!(set.len(), 3);
+
+        let order = runtime.block_on(async move {
+            let mut order = Vec::new();
+            while let Some(result) = set.join_next().await {
+                order.push(result.unwrap());
+            }
+            order
+        });
+        assert_eq!(order, vec![10, 20, 30]);
+    }
+
+    #[test]
+    fn test_join_set_drop_aborts_tasks() {
+        use cycle::task::JoinSet;
+
+        let runtime = Runtime::with_workers(1).unwrap();
+        let (tx, rx) = oneshot::channel::<()>();
+        let mut set = JoinSet::new();
+        set.spawn_on(async move {
+            let _ = rx.await;
+        }, &runtime);
+
+        // Dropping the set drops the pending task, and with it the receiver
+        drop(set);
+        runtime.block_on(async move {
+            let mut tx = tx;
+            tx.cancellation().await;
+        });
+    }
+
+    #[test]
+    fn test_scope_children_borrow_stack_data() {
+        let mut counts = vec![0usize; 4];
+        let words = ["a", "bb", "ccc", "dddd"];
+
+        cycle::scope(|s| {
+            let words = &words;
+            let slots = counts.iter_mut();
+            async move {
+                for (slot, word) in slots.zip(words.iter()) {
+                    s.spawn(async move {
+                        cycle::time::yield_now().await;
+                        *slot = word.len();
+                    });
+                }
+            }
+        });
+
+        // Every child finished before `scope` returned
+        assert_eq!(counts, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_scope_child_spawns_after_body_returns() {
+        let mut grandchild = 0;
+
+        cycle::scope(|s| {
+            let grandchild = &mut grandchild;
+            let spawner = s.clone();
+            async move {
+                s.spawn(async move {
+                    // Still spawning well after the body below has returned
+                    for _ in 0..10 {
+                        cycle::time::yield_now().await;
+                    }
+                    spawner.spawn(async move {
+                        *grandchild = 7;
+                    });
+                });
+            }
+        });
+
+        assert_eq!(grandchild, 7);
+    }
+
+    #[test]
+    fn test_scope_drives_current_thread_runtime() {
+        use cycle::runtime::Builder;
+
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        let mut done = false;
+
+        runtime.block_on(async {
+            // The body returns without awaiting its child, which only runs
+            // while the scope waits
+            cycle::scope(|s| {
+                let done = &mut done;
+                async move {
+                    s.spawn(async move {
+                        cycle::time::yield_now().await;
+                        *done = true;
+                    });
+                }
+            });
+        });
+
+        assert!(done);
+    }
+
+    #[test]
+    fn test_scope_spawn_on_closed_runtime_is_cancelled() {
+        let runtime = Runtime::with_workers(1).unwrap();
+        let handle = runtime.handle().clone();
+        drop(runtime);
+
+        // The child is dropped as soon as it is spawned
+        let _guard = handle.enter();
+        let cancelled = cycle::scope(|s| async move {
+            s.spawn(async { 1 }).await.unwrap_err().is_cancelled()
+        });
+        assert!(cancelled);
+    }
+
+    #[test]
+    fn test_spawn_targets_current_runtime() {
+        use cycle::runtime::{Builder, Handle};
+
+        assert!(Handle::try_current().is_err());
+
+        let runtime = Builder::new()
+            .worker_threads(1)
+            .thread_name("current-rt")
+            .build()
+            .unwrap();
+
+        // Spawned from a worker: the child lands on the same runtime
+        let name = runtime.block_on(async {
+            cycle::spawn(async { std::thread::current().name().unwrap().to_string() })
+                .await
+                .unwrap()
+        });
+        assert!(name.starts_with("current-rt-"));
+
+        // Entered from outside: free functions resolve to it too
+        let guard = runtime.enter();
+        let handle = Handle::current();
+        let name = runtime.block_on(handle.spawn(async {
+            std::thread::current().name().unwrap().to_string()
+        }));
+        assert!(name.unwrap().starts_with("current-rt-"));
+        drop(guard);
+        assert!(Handle::try_current().is_err());
+    }
+
+    #[test]
+    fn test_block_on_polls_borrowed_non_send_future() {
+        use std::rc::Rc;
+
+        let runtime = Runtime::with_workers(2).unwrap();
+        let caller = std::thread::current().id();
+        let local = Rc::new(vec![1, 2, 3]);
+        let borrowed = &local;
+
+        let (on_caller, sum) = runtime.block_on(async {
+            // Spawned work still runs on the pool
+            let doubled = cycle::spawn(async { 21 * 2 }).await.unwrap();
+            (std::thread::current().id(), borrowed.iter().sum::<i32>() + doubled)
+        });
+
+        assert_eq!(on_caller, caller);
+        assert_eq!(sum, 48);
+    }
+
+    #[test]
+    fn test_budget_forces_busy_task_to_yield() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let runtime = Runtime::with_workers(1).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // Never awaits anything that is actually pending
+        let busy = runtime.spawn({
+            let stop = stop.clone();
+            async move {
+                let mut iterations = 0u64;
+                while !stop.load(Ordering::Acquire) {
+                    cycle::task::consume_budget().await;
+                    iterations += 1;
+                }
+                iterations
+            }
+        });
+        runtime.spawn(async move {
+            stop.store(true, Ordering::Release);
+        });
+
+        let iterations = runtime.block_on(busy).unwrap();
+        assert!(iterations > 0);
+
+        // Opting out never yields, but still finishes
+        let total = runtime.block_on(cycle::task::unconstrained(async {
+            for _ in 0..1_000 {
+                cycle::task::consume_budget().await;
+            }
+            1_000
+        }));
+        assert_eq!(total, 1_000);
+    }
+
+    #[test]
+    fn test_priorities_served_in_order_with_background_share() {
+        use cycle::task::Priority;
+        use std::sync::{Arc, Mutex};
+
+        let runtime = Runtime::with_workers(1).unwrap();
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        // Hold the only worker until every task is queued
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let (started_tx, started_rx) = mpsc::channel::<()>();
+        runtime.spawn(async move {
+            started_tx.send(()).unwrap();
+            gate_rx.recv().unwrap();
+        });
+        started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let mut handles = Vec::new();
+        for (priority, count) in [(Priority::Background, 2), (Priority::Normal, 20), (Priority::High, 5)] {
+            for _ in 0..count {
+                let order = order.clone();
+                handles.push(runtime.spawn_with_priority(priority, async move {
+                    order.lock().unwrap().push(priority);
+                }));
+            }
+        }
+        gate_tx.send(()).unwrap();
+
+        runtime.block_on(async {
+            for handle in handles {
+                handle.await.unwrap();
+            }
+        });
+
+        let order = order.lock().unwrap();
+        assert!(order[..5].iter().all(|p| *p == Priority::High));
+        let last_normal = order.iter().rposition(|p| *p == Priority::Normal).unwrap();
+        let first_background = order.iter().position(|p| *p == Priority::Background).unwrap();
+        assert!(first_background < last_normal, "background tasks were starved");
+    }
+
+    #[test]
+    fn test_task_builder_names_and_ids() {
+        use cycle::task;
+
+        let runtime = Runtime::with_workers(2).unwrap();
+
+        let named = task::Builder::new()
+            .name("conn-handler")
+            .spawn_on(async { task::id() }, runtime.handle());
+        let line = line!() - 1;
+        let anonymous = runtime.spawn(async { task::try_id() });
+        let blocking = runtime.spawn_blocking(task::id);
+
+        assert_eq!(named.name(), Some("conn-handler"));
+        assert_eq!(anonymous.name(), None);
+        assert_eq!(named.spawn_location().file(), file!());
+        assert_eq!(named.spawn_location().line(), line);
+        assert_ne!(named.id(), anonymous.id());
+
+        let (named_id, anonymous_id, blocking_id) = (named.id(), anonymous.id(), blocking.id());
+        runtime.block_on(async move {
+            assert_eq!(task::try_id(), None);
+            assert_eq!(named.await.unwrap(), named_id);
+            assert_eq!(anonymous.await.unwrap(), Some(anonymous_id));
+            assert_eq!(blocking.await.unwrap(), blocking_id);
+        });
+    }
+
+    cycle::task_local! {
+        static TRACE_ID: u64;
+    }
+
+    #[test]
+    fn test_task_local_follows_task_across_workers() {
+        let runtime = Runtime::with_workers(4).unwrap();
+
+        let handles: Vec<_> = (0..16u64)
+            .map(|i| {
+                runtime.spawn(TRACE_ID.scope(i, async move {
+                    for _ in 0..50 {
+                        cycle::time::yield_now().await;
+                        assert_eq!(TRACE_ID.get(), i);
+                    }
+
+                    // Spawned tasks don't inherit the value
+                    let child = cycle::spawn(async { TRACE_ID.try_with(|id| *id).is_err() });
+                    child.await.unwrap()
+                }))
+            })
+            .collect();
+
+        runtime.block_on(async {
+            for handle in handles {
+                assert!(handle.await.unwrap());
+            }
+        });
+
+        assert!(TRACE_ID.try_with(|_| ()).is_err());
+        assert_eq!(TRACE_ID.sync_scope(7, || TRACE_ID.sync_scope(8, || TRACE_ID.get()) + TRACE_ID.get()), 15);
+    }
+
+    #[cycle::test]
+    async fn test_macro_defaults_to_current_thread() -> std::io::Result<()> {
+        use cycle::runtime::{Handle, RuntimeFlavor};
+
+        assert_eq!(Handle::current().flavor(), RuntimeFlavor::CurrentThread);
+        let value = cycle::spawn(async { 21 * 2 }).await?;
+        assert_eq!(value, 42);
+        Ok(())
+    }
+
+    #[cycle::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_macro_multi_thread() {
+        use cycle::runtime::{Handle, RuntimeFlavor};
+
+        assert_eq!(Handle::current().flavor(), RuntimeFlavor::MultiThread);
+        let handles: Vec<_> = (0..8).map(|i| cycle::spawn(async move { i })).collect();
+        let mut total = 0;
+        for handle in handles {
+            total += handle.await.unwrap();
+        }
+        assert_eq!(total, 28);
+    }
+
+    #[cycle::test(start_paused = true)]
+    async fn test_paused_clock_auto_advances() {
+        use cycle::time;
+
+        let real = Instant::now();
+        let start = time::now();
+
+        time::sleep(Duration::from_secs(3600)).await;
+        assert!(time::now() - start >= Duration::from_secs(3600));
+
+        let result = time::timeout(Duration::from_secs(1), time::sleep(Duration::from_secs(60))).await;
+        assert!(result.is_err());
+
+        // Sleeps in spawned tasks complete in deadline order
+        let order = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let handles: Vec<_> = [30u64, 10, 20]
+            .into_iter()
+            .map(|secs| {
+                let order = order.clone();
+                cycle::spawn(async move {
+                    time::sleep(Duration::from_secs(secs)).await;
+                    order.lock().unwrap().push(secs);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), [10, 20, 30]);
+
+        assert!(real.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cycle::test(start_paused = true)]
+    async fn test_dropped_sleeps_leave_paused_clock() {
+        use cycle::time;
+
+        let start = time::now();
+
+        // Polled several times by the timeout, then dropped with it
+        let result = time::timeout(Duration::from_secs(1), async {
+            let mut long = std::pin::pin!(time::sleep(Duration::from_secs(3600)));
+            for _ in 0..3 {
+                assert!(futures::poll!(long.as_mut()).is_pending());
+                time::yield_now().await;
+            }
+            std::future::pending::<()>().await
+        })
+        .await;
+        assert!(result.is_err());
+
+        // Nothing is left to auto-advance to while waiting on a real thread
+        let (tx, rx) = oneshot::channel();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            tx.send(()).unwrap();
+        });
+        rx.await.unwrap();
+        assert!(time::now() - start < Duration::from_secs(2));
+    }
+
+    #[cycle::test]
+    async fn test_pause_and_advance() {
+        use cycle::time;
+
+        time::pause();
+        let sleep = cycle::spawn(time::sleep(Duration::from_secs(10)));
+
+        time::advance(Duration::from_secs(5)).await;
+        assert!(!sleep.is_finished());
+
+        time::advance(Duration::from_secs(5)).await;
+        sleep.await.unwrap();
+
+        time::resume();
+        let start = Instant::now();
+        time::sleep(Duration::from_millis(20)).await;
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[cycle::test(start_paused = true)]
+    async fn test_select_branches() {
+        use cycle::time::sleep;
+
+        let (tx, rx) = oneshot::channel::<u32>();
+        tx.send(7).unwrap();
+
+        let value = select! {
+            _ = sleep(Duration::from_secs(1)) => 0,
+            value = rx => value.unwrap(),
+        };
+        assert_eq!(value, 7);
+
+        // Disabled preconditions and mismatched patterns fall through to `else`
+        let mut polled = 0;
+        let value = select! {
+            biased;
+
+            Some(n) = async { None::<u32> } => n,
+            _ = async { polled += 1 }, if false => 1,
+            else => 2,
+        };
+        assert_eq!((value, polled), (2, 0));
+
+        // Handlers may break out of the enclosing loop
+        let mut ticks = 0;
+        loop {
+            select! {
+                _ = sleep(Duration::from_secs(1)) => {
+                    ticks += 1;
+                    if ticks == 3 {
+                        break;
+                    }
+                }
+            }
+        }
+        assert_eq!(ticks, 3);
+    }
+
+    #[cycle::test]
+    async fn test_select_loop_yields_to_other_tasks() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let flag = Arc::new(AtomicBool::new(false));
+        let setter = flag.clone();
+        cycle::spawn(async move { setter.store(true, Ordering::Release) });
+
+        // Both branches are always ready, so only the budget lets the spawned task run
+        while !flag.load(Ordering::Acquire) {
+            select! {
+                _ = std::future::ready(()) => {}
+                _ = cycle::task::consume_budget() => {}
+            }
+        }
+    }
+
+    #[cycle::test(start_paused = true)]
+    async fn test_join_and_try_join() {
+        use cycle::time::sleep;
+
+        let name = String::from("cycle");
+        let (len, doubled, ()) = join!(
+            async { name.len() },
+            async {
+                sleep(Duration::from_secs(1)).await;
+                21 * 2
+            },
+            sleep(Duration::from_secs(2)),
+        );
+        assert_eq!((len, doubled), (5, 42));
+        assert_eq!(join!(), ());
+
+        let ok: Result<(u32, &str), String> = try_join!(async { Ok(1) }, async { Ok("two") });
+        assert_eq!(ok, Ok((1, "two")));
+
+        // The first error wins and the slower future is dropped
+        let (tx, rx) = oneshot::channel::<()>();
+        let err: Result<((), ()), String> = try_join!(
+            async move {
+                let _tx = tx;
+                sleep(Duration::from_secs(3600)).await;
+                Ok(())
+            },
+            async {
+                sleep(Duration::from_secs(1)).await;
+                Err("failed".to_string())
+            },
+        );
+        assert_eq!(err, Err("failed".to_string()));
+        assert!(rx.await.is_err());
+    }
+
+    #[test]
+    fn test_shutdown_timeout_drains_then_cancels() {
+        use cycle::runtime::Builder;
+        use cycle::time::sleep;
+        use std::time::Instant;
+
+        let runtime = Builder::new().worker_threads(2).enable_all().build().unwrap();
+        let handle = runtime.handle().clone();
+
+        let quick = runtime.spawn(async {
+            sleep(Duration::from_millis(20)).await;
+            1
+        });
+        let (tx, rx) = oneshot::channel::<()>();
+        let stuck = runtime.spawn(async move {
+            let _tx = tx;
+            std::future::pending::<()>().await;
+        });
+
+        let start = Instant::now();
+        runtime.shutdown_timeout(Duration::from_millis(200));
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        // The quick task finished within the deadline, the stuck one was dropped
+        assert_eq!(futures::executor::block_on(quick).unwrap(), 1);
+        assert!(futures::executor::block_on(stuck).unwrap_err().is_cancelled());
+        assert!(futures::executor::block_on(rx).is_err());
+
+        // Spawns after shutdown are rejected
+        let late = handle.spawn(async { 2 });
+        assert!(futures::executor::block_on(late).unwrap_err().is_cancelled());
+    }
+
+    #[test]
+    fn test_shutdown_does_not_wait_for_blocking_jobs() {
+        use std::time::Instant;
+
+        for timeout in [None, Some(Duration::from_millis(100))] {
+            let runtime = Runtime::with_workers(1).unwrap();
+            let (started_tx, started_rx) = mpsc::channel();
+            let _job = runtime.spawn_blocking(move || {
+                started_tx.send(()).unwrap();
+                std::thread::sleep(Duration::from_secs(3));
+            });
+            started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+            // The long job is left behind instead of holding up the shutdown
+            let start = Instant::now();
+            match timeout {
+                Some(timeout) => runtime.shutdown_timeout(timeout),
+                None => runtime.shutdown_background(),
+            }
+            assert!(start.elapsed() < Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn test_panic_shutdown_cancels_siblings() {
+        use cycle::runtime::{Builder, UnhandledPanic};
+
+        let runtime = Builder::new()
+            .worker_threads(2)
+            .unhandled_panic(UnhandledPanic::ShutdownRuntime)
+            .build()
+            .unwrap();
+
+        let sibling = runtime.spawn(std::future::pending::<()>());
+        let panicked = runtime.spawn(async { panic!("boom") });
+
+        assert!(runtime.block_on(panicked).unwrap_err().is_panic());
+        assert!(runtime.block_on(sibling).unwrap_err().is_cancelled());
+    }
+
+    #[test]
+    fn test_drop_runtime_cancels_tasks() {
+        use cycle::runtime::Builder;
+        use cycle::time::sleep;
+
+        let other = Builder::new().worker_threads(1).enable_all().build().unwrap();
+        let runtime = Builder::new().worker_threads(2).enable_all().build().unwrap();
+
+        let (tx, rx) = oneshot::channel::<()>();
+        let sleeper = runtime.spawn(async move {
+            let _tx = tx;
+            sleep(Duration::from_secs(3600)).await;
+        });
+        drop(runtime);
+
+        assert!(futures::executor::block_on(sleeper).unwrap_err().is_cancelled());
+        assert!(futures::executor::block_on(rx).is_err());
+
+        // Dropping one runtime leaves the timers of the others running
+        other.block_on(sleep(Duration::from_millis(10)));
+        other.shutdown_background();
+    }
+
+    #[test]
+    fn test_runtimes_own_their_drivers() {
+        use cycle::net::TcpListener;
+        use cycle::runtime::Builder;
+        use cycle::time::sleep;
+
+        let survivor = Builder::new().worker_threads(1).enable_all().build().unwrap();
+
+        // Runtimes can come and go without affecting each other's timers
+        for _ in 0..10 {
+            let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+            runtime.block_on(sleep(Duration::from_millis(1)));
+        }
+        survivor.block_on(sleep(Duration::from_millis(1)));
+
+        // Sockets stay bound to the runtime they were created on
+        let doomed = Builder::new().worker_threads(1).enable_all().build().unwrap();
+        let listener = doomed.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        drop(doomed);
+        assert!(survivor.block_on(listener.accept()).is_err());
+        survivor.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+
+        // Drivers have to be enabled to be used
+        let no_io = Builder::new_current_thread().build().unwrap();
+        assert!(no_io.block_on(TcpListener::bind("127.0.0.1:0")).is_err());
+    }
+
+    #[cycle::test]
+    async fn test_tcp_readiness_is_not_lost() {
+        use cycle::io::{AsyncReadExt, AsyncWriteExt};
+        use cycle::net::{TcpListener, TcpStream};
+        use cycle::time::sleep;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = cycle::spawn(async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(b"ping").await.unwrap();
+
+            let mut reply = String::new();
+            stream.read_to_string(&mut reply).await.unwrap();
+            reply
+        });
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        // The data arrives before anyone waits for it; the readiness is kept
+        sleep(Duration::from_millis(20)).await;
+        let mut buf = [0u8; 4];
+        let mut read = 0;
+        while read < buf.len() {
+            let n = stream.read(&mut buf[read..]).await.unwrap();
+            assert_ne!(n, 0, "connection closed early");
+            read += n;
+        }
+        assert_eq!(&buf, b"ping");
+
+        stream.write_all(b"pong").await.unwrap();
+        drop(stream);
+        assert_eq!(client.await.unwrap(), "pong");
+    }
+
+    #[cycle::test]
+    async fn test_dropped_sockets_recycle_registrations() {
+        use cycle::net::UdpSocket;
+
+        // Every round reuses the slots freed by the last one, including a
+        // socket dropped while its data was still unread
+        for round in 0..64u8 {
+            let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let unread = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let receiver_addr = receiver.local_addr().unwrap();
+
+            sender.send_to(&[round], unread.local_addr().unwrap()).await.unwrap();
+            sender.send_to(&[round], receiver_addr).await.unwrap();
+
+            let mut buf = [0u8; 1];
+            let (n, _) = receiver.recv_from(&mut buf).await.unwrap();
+            assert_eq!((n, buf[0]), (1, round));
+        }
+    }
+
+    #[test]
+    fn test_idle_workers_drive_timers_and_io() {
+        use cycle::net::UdpSocket;
+        use cycle::runtime::Builder;
+        use cycle::time::sleep;
+
+        let runtimes = [
+            Builder::new().worker_threads(2).enable_all().build().unwrap(),
+            Builder::new_current_thread().enable_all().build().unwrap(),
+        ];
+
+        for runtime in &runtimes {
+            // Let the workers go idle, one of them blocking on the drivers
+            // with no timer to bound the wait
+            std::thread::sleep(Duration::from_millis(20));
+
+            runtime.block_on(async {
+                // Registered from outside the workers; has to cut that wait short
+                let start = Instant::now();
+                sleep(Duration::from_millis(5)).await;
+                assert!(start.elapsed() < Duration::from_secs(5));
+
+                let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                let addr = receiver.local_addr().unwrap();
+                let sender = cycle::spawn(async move {
+                    sleep(Duration::from_millis(5)).await;
+                    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                    socket.send_to(b"x", addr).await.unwrap();
+                });
+
+                let mut buf = [0u8; 1];
+                assert_eq!(receiver.recv_from(&mut buf).await.unwrap().0, 1);
+                sender.await.unwrap();
+            });
+        }
+    }
+
+    #[cycle::test]
+    async fn test_concurrent_accepts_are_all_woken() {
+        use cycle::net::{TcpListener, TcpStream};
+        use cycle::time::timeout;
+        use std::sync::Arc;
+
+        let listener = Arc::new(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        let addr = listener.local_addr().unwrap();
+
+        // Both accepters wait on the listener before anyone connects
+        let accepters: Vec<_> = (0..2)
+            .map(|_| {
+                let listener = listener.clone();
+                cycle::spawn(async move { listener.accept().await.map(|_| ()) })
+            })
+            .collect();
+        cycle::time::yield_now().await;
+
+        let _first = TcpStream::connect(addr).await.unwrap();
+        let _second = TcpStream::connect(addr).await.unwrap();
+        for accepter in accepters {
+            timeout(Duration::from_secs(5), accepter).await.unwrap().unwrap().unwrap();
+        }
+    }
+
+    #[cycle::test]
+    async fn test_failed_connects_release_registrations() {
+        use cycle::net::{TcpListener, TcpStream};
+        use cycle::runtime::Handle;
+        use cycle::time::timeout;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let sources = Handle::current().stats().io_sources;
+
+        // Cancelled while waiting for the connection to complete
+        for _ in 0..10 {
+            let _ = timeout(Duration::ZERO, TcpStream::connect(addr)).await;
+        }
+        assert_eq!(Handle::current().stats().io_sources, sources);
+
+        // Refused once nothing listens on the port any more
+        drop(listener);
+        for _ in 0..10 {
+            assert!(TcpStream::connect(addr).await.is_err());
+        }
+        assert_eq!(Handle::current().stats().io_sources, sources - 1);
+    }
+}